@@ -10,11 +10,13 @@ use crate::components::audio_source_selector::AudioSourceSelector;
 use crate::components::transcription::TranscriptionPanel;
 use crate::components::translation::TranslationPanel;
 use crate::components::language_selector::LanguageSelector;
+use crate::components::settings::Settings;
 use crate::state::{AppState, AudioSource, ModelStatus, RecordingState};
 use crate::workers::audio_capture;
 #[cfg(feature = "extension")]
 use crate::workers::tab_capture;
 use crate::workers::mixed_capture;
+use crate::workers::bridge::{self, WorkerMessage};
 
 #[component]
 pub fn App() -> impl IntoView {
@@ -23,6 +25,7 @@ pub fn App() -> impl IntoView {
     let whisper_status = state.whisper_status;
     let recording_state = state.recording_state;
     let audio_source = state.audio_source;
+    let separate_channels = state.separate_channels;
     provide_context(state);
 
     // Global keyboard shortcuts
@@ -44,12 +47,19 @@ pub fn App() -> impl IntoView {
                 event.prevent_default();
                 match recording_state.get_untracked() {
                     RecordingState::Idle => {
+                        // Before any audio, so none lands on the last recording
+                        bridge::reset_whisper_streams();
                         spawn_local(async move {
                             let result = match audio_source.get_untracked() {
                                 AudioSource::Microphone => audio_capture::start_recording().await,
                                 #[cfg(feature = "extension")]
                                 AudioSource::TabAudio => tab_capture::start_tab_capture().await,
-                                AudioSource::Both => mixed_capture::start_mixed_capture().await,
+                                AudioSource::Both => {
+                                    mixed_capture::start_mixed_capture(
+                                        separate_channels.get_untracked(),
+                                    )
+                                    .await
+                                }
                             };
                             match result {
                                 Ok(()) => recording_state.set(RecordingState::Recording),
@@ -64,6 +74,8 @@ pub fn App() -> impl IntoView {
                             AudioSource::TabAudio => tab_capture::stop_tab_capture(),
                             AudioSource::Both => mixed_capture::stop_mixed_capture(),
                         }
+                        // Commit what was still tentative rather than drop it
+                        bridge::send_to_whisper(&WorkerMessage::Flush);
                         recording_state.set(RecordingState::Idle);
                     }
                     _ => {}
@@ -101,6 +113,7 @@ pub fn App() -> impl IntoView {
 
                 <ModelLoader />
                 <LanguageSelector />
+                <Settings />
                 <AudioSourceSelector />
                 <AudioRecorder />
 
@@ -6,6 +6,12 @@ use crate::workers::audio_capture;
 #[cfg(feature = "extension")]
 use crate::workers::tab_capture;
 use crate::workers::mixed_capture;
+use crate::workers::bridge::{self, WorkerMessage};
+
+// Warn once more than one sample in a thousand is clipped
+const CLIPPING_WARNING: f32 = 0.001;
+// Requests queued behind the running decode before we say it can't keep up
+const BACKLOG_WARNING: usize = 4;
 
 #[component]
 pub fn AudioRecorder() -> impl IntoView {
@@ -16,18 +22,28 @@ pub fn AudioRecorder() -> impl IntoView {
     let error_message = state.error_message;
     let audio_level = state.audio_level;
     let recording_duration = state.recording_duration;
+    let input_clipping = state.input_clipping;
+    let clipping = move || input_clipping.get() > CLIPPING_WARNING;
+    let queue_depth = state.whisper_queue_depth;
+    let backlog = move || queue_depth.get() > BACKLOG_WARNING;
 
     let audio_source = state.audio_source;
+    let separate_channels = state.separate_channels;
 
     let toggle_recording = move |_| {
         spawn_local(async move {
             match recording_state.get_untracked() {
                 RecordingState::Idle => {
+                    // Before any audio, so none lands on the last recording
+                    bridge::reset_whisper_streams();
                     let result = match audio_source.get_untracked() {
                         AudioSource::Microphone => audio_capture::start_recording().await,
                         #[cfg(feature = "extension")]
                         AudioSource::TabAudio => tab_capture::start_tab_capture().await,
-                        AudioSource::Both => mixed_capture::start_mixed_capture().await,
+                        AudioSource::Both => {
+                            mixed_capture::start_mixed_capture(separate_channels.get_untracked())
+                                .await
+                        }
                     };
                     match result {
                         Ok(()) => {
@@ -45,6 +61,8 @@ pub fn AudioRecorder() -> impl IntoView {
                         AudioSource::TabAudio => tab_capture::stop_tab_capture(),
                         AudioSource::Both => mixed_capture::stop_mixed_capture(),
                     }
+                    // Commit what was still tentative rather than drop it
+                    bridge::send_to_whisper(&WorkerMessage::Flush);
                     recording_state.set(RecordingState::Idle);
                 }
                 RecordingState::Processing => {}
@@ -99,6 +117,19 @@ pub fn AudioRecorder() -> impl IntoView {
                             <p class="text-xs text-center text-gray-500 dark:text-gray-400">
                                 {move || format!("{:.1}s", recording_duration.get())}
                             </p>
+                            <Show when=clipping>
+                                <p class="text-xs text-center text-amber-600 dark:text-amber-400">
+                                    "Input is clipping; turn the input volume down."
+                                </p>
+                            </Show>
+                            <Show when=backlog>
+                                <p class="text-xs text-center text-amber-600 dark:text-amber-400">
+                                    {move || format!(
+                                        "Transcription is falling behind ({} requests queued); try a smaller model.",
+                                        queue_depth.get(),
+                                    )}
+                                </p>
+                            </Show>
                         </div>
                     })
                 } else {
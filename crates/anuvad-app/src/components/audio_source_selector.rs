@@ -6,6 +6,8 @@ use crate::state::{AppState, AudioSource};
 pub fn AudioSourceSelector() -> impl IntoView {
     let state = expect_context::<AppState>();
     let audio_source = state.audio_source;
+    let separate_channels = state.separate_channels;
+    let both_selected = move || audio_source.get() == AudioSource::Both;
 
     let mic_class = move || {
         let base = "px-4 py-2 text-sm font-medium rounded-lg transition-all duration-150";
@@ -61,6 +63,19 @@ pub fn AudioSourceSelector() -> impl IntoView {
             >
                 "Both"
             </button>
+            <Show when=both_selected>
+                <label
+                    class="flex items-center gap-1 text-sm text-gray-500 dark:text-gray-400 ml-1"
+                    title="Tag your microphone as Me and the tab or system audio as Remote"
+                >
+                    <input
+                        type="checkbox"
+                        prop:checked=move || separate_channels.get()
+                        on:change=move |ev| separate_channels.set(event_target_checked(&ev))
+                    />
+                    "Separate me and remote"
+                </label>
+            </Show>
         </div>
     }
 }
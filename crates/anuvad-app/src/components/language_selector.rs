@@ -2,6 +2,7 @@ use leptos::prelude::*;
 use leptos::ev;
 
 use crate::state::AppState;
+use crate::workers::bridge::{self, WorkerMessage};
 
 const LANGUAGES: &[(&str, &str)] = &[
     ("auto", "Auto-detect"),
@@ -63,6 +64,30 @@ pub fn LanguageSelector() -> impl IntoView {
         state.target_language.set(target);
     };
 
+    // Comma-separated codes, e.g. "hi, en, mr"
+    let on_allowed_change = move |ev: ev::Event| {
+        let codes = event_target_value(&ev)
+            .split(',')
+            .map(|c| c.trim().to_lowercase())
+            .filter(|c| !c.is_empty())
+            .collect();
+        state.allowed_languages.set(codes);
+    };
+
+    // Keep the whisper worker in sync with the chosen source language,
+    // abandoning any decode still using the old one. The first run only
+    // sends the initial choice.
+    Effect::new(move |previous: Option<()>| {
+        let msg = WorkerMessage::SetLanguage {
+            language: state.source_language.get(),
+            allowed_languages: state.allowed_languages.get(),
+        };
+        if previous.is_some() {
+            bridge::cancel_whisper();
+        }
+        bridge::send_to_whisper(&msg);
+    });
+
     view! {
         <div class="card">
             <div class="flex flex-col sm:flex-row items-center gap-4">
@@ -84,6 +109,17 @@ pub fn LanguageSelector() -> impl IntoView {
                             }
                         }).collect::<Vec<_>>()}
                     </select>
+                    {move || {
+                        (state.source_language.get() == "auto").then(|| view! {
+                            <input
+                                type="text"
+                                class="w-full mt-2 px-3 py-2 bg-gray-100 dark:bg-gray-800 border border-gray-300 dark:border-gray-700 rounded-lg text-sm focus:ring-2 focus:ring-indigo-500 focus:border-transparent"
+                                placeholder="Limit detection to, e.g. hi, en, mr"
+                                prop:value=move || state.allowed_languages.get().join(", ")
+                                on:change=on_allowed_change
+                            />
+                        })
+                    }}
                 </div>
 
                 <div class="hidden sm:flex items-center pt-6">
@@ -1,15 +1,36 @@
 use leptos::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 
-use crate::state::{AppState, ModelStatus};
+use crate::state::{AppState, ModelStatus, WhisperModel};
+use crate::workers::bridge::{LoadReport, WeightPrecision};
 use crate::workers::model_cache;
 
+const MB: f64 = 1024.0 * 1024.0;
+
+// e.g. "Loaded in 3.2 s · F16 weights · 610 MB peak memory"
+fn load_summary(report: &LoadReport) -> String {
+    let weights = if report.quantized {
+        "quantized"
+    } else {
+        report.precision.label()
+    };
+    let seconds = report.load_ms / 1000.0;
+    let mut summary = format!("Loaded in {seconds:.1} s \u{2022} {weights} weights");
+    if let Some(bytes) = report.peak_memory_bytes {
+        summary.push_str(&format!(" \u{2022} {:.0} MB peak memory", bytes as f64 / MB));
+    }
+    summary
+}
+
 #[component]
 pub fn ModelLoader() -> impl IntoView {
     let state = expect_context::<AppState>();
 
     let whisper_status = state.whisper_status;
+    let whisper_model = state.whisper_model;
+    let whisper_precision = state.whisper_precision;
     let whisper_progress = state.whisper_progress;
+    let whisper_load_report = state.whisper_load_report;
     let translator_status = state.translator_status;
     let translator_progress = state.translator_progress;
     let error_message = state.error_message;
@@ -17,20 +38,45 @@ pub fn ModelLoader() -> impl IntoView {
     let download_whisper = move |_| {
         spawn_local(async move {
             whisper_status.set(ModelStatus::Downloading);
-            match model_cache::download_whisper_model(
+            if let Err(e) = model_cache::download_whisper_model(
+                whisper_model.get_untracked(),
                 move |p| whisper_progress.set(p),
             ).await {
-                Ok(()) => {
-                    whisper_status.set(ModelStatus::Ready);
-                }
-                Err(e) => {
-                    whisper_status.set(ModelStatus::Error);
-                    error_message.set(Some(format!("Whisper download failed: {e}")));
-                }
+                whisper_status.set(ModelStatus::Error);
+                error_message.set(Some(format!("Whisper download failed: {e}")));
+                return;
+            }
+            // Ready once the worker reports ModelLoaded
+            whisper_status.set(ModelStatus::Loading);
+            if let Err(e) = model_cache::load_whisper_model(
+                whisper_model.get_untracked(),
+                whisper_precision.get_untracked(),
+            ).await {
+                whisper_status.set(ModelStatus::Error);
+                error_message.set(Some(format!("Whisper load failed: {e}")));
             }
         });
     };
 
+    // A different checkpoint has to be downloaded before it is ready
+    let on_model_change = move |ev: leptos::ev::Event| {
+        if let Some(model) = WhisperModel::from_id(&event_target_value(&ev)) {
+            let precision = whisper_precision.get_untracked();
+            if model.needs_half_precision() && precision == WeightPrecision::F32 {
+                whisper_precision.set(WeightPrecision::F16);
+            }
+            whisper_model.set(model);
+            whisper_status.set(ModelStatus::NotDownloaded);
+            whisper_load_report.set(None);
+        }
+    };
+
+    let on_precision_change = move |ev: leptos::ev::Event| {
+        if let Some(precision) = WeightPrecision::from_id(&event_target_value(&ev)) {
+            whisper_precision.set(precision);
+        }
+    };
+
     let download_translator = move |_| {
         spawn_local(async move {
             translator_status.set(ModelStatus::Downloading);
@@ -56,8 +102,10 @@ pub fn ModelLoader() -> impl IntoView {
                 <div class="space-y-3">
                     <div class="flex items-center justify-between">
                         <div>
-                            <h3 class="font-medium">"Whisper Small"</h3>
-                            <p class="text-xs text-gray-500 dark:text-gray-400">"~460 MB \u{2022} Speech recognition"</p>
+                            <h3 class="font-medium">{move || whisper_model.get().label()}</h3>
+                            <p class="text-xs text-gray-500 dark:text-gray-400">
+                                {move || format!("{} \u{2022} Speech recognition", whisper_model.get().size())}
+                            </p>
                         </div>
                         <span class={move || whisper_status.get().badge_class()}>
                             {move || whisper_status.get().label()}
@@ -84,9 +132,59 @@ pub fn ModelLoader() -> impl IntoView {
                             }
                             ModelStatus::NotDownloaded | ModelStatus::Error => {
                                 view! {
-                                    <button class="btn-primary w-full text-sm" on:click=download_whisper>
-                                        "Download Whisper"
-                                    </button>
+                                    <div class="space-y-2">
+                                        <select
+                                            class="w-full px-2 py-1 bg-gray-100 dark:bg-gray-800 border border-gray-300 dark:border-gray-700 rounded-lg text-sm"
+                                            on:change=on_model_change
+                                        >
+                                            {WhisperModel::ALL.into_iter().map(|model| {
+                                                view! {
+                                                    <option
+                                                        value=model.id()
+                                                        selected=move || whisper_model.get() == model
+                                                    >
+                                                        {format!("{} ({})", model.label(), model.size())}
+                                                    </option>
+                                                }
+                                            }).collect::<Vec<_>>()}
+                                        </select>
+                                        <label class="flex items-center justify-between gap-2 text-xs text-gray-500 dark:text-gray-400">
+                                            "Weights"
+                                            <select
+                                                class="px-2 py-1 bg-gray-100 dark:bg-gray-800 border border-gray-300 dark:border-gray-700 rounded-lg text-sm"
+                                                on:change=on_precision_change
+                                            >
+                                                {WeightPrecision::ALL.into_iter().map(|precision| {
+                                                    view! {
+                                                        <option
+                                                            value=precision.id()
+                                                            selected=move || whisper_precision.get() == precision
+                                                            disabled=move || {
+                                                                let model = whisper_model.get();
+                                                                precision == WeightPrecision::F32
+                                                                    && model.needs_half_precision()
+                                                            }
+                                                        >
+                                                            {precision.label()}
+                                                        </option>
+                                                    }
+                                                }).collect::<Vec<_>>()}
+                                            </select>
+                                        </label>
+                                        <p class="text-xs text-gray-500 dark:text-gray-400">
+                                            "F16 and BF16 halve the memory of F32 at some speed cost"
+                                        </p>
+                                        <button class="btn-primary w-full text-sm" on:click=download_whisper>
+                                            "Download Whisper"
+                                        </button>
+                                    </div>
+                                }.into_any()
+                            }
+                            ModelStatus::Ready => {
+                                view! {
+                                    <p class="text-xs text-gray-500 dark:text-gray-400">
+                                        {move || whisper_load_report.get().as_ref().map(load_summary)}
+                                    </p>
                                 }.into_any()
                             }
                             _ => view! { <div></div> }.into_any()
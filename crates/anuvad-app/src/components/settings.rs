@@ -1,11 +1,342 @@
 use leptos::prelude::*;
+use leptos::ev;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+
+use crate::state::{AppState, DecodingQuality};
+use crate::workers::bridge::{
+    self, DiarizationOptions, DspOptions, Normalization, PromptOptions, SafeguardOptions,
+    WhisperTask, WorkerMessage,
+};
+
+// Logit boost for hotword tokens; enough to win close calls without forcing
+// the words everywhere
+const HOTWORD_BIAS: f32 = 2.0;
+// Same safeguard thresholds as the reference implementation
+const NO_SPEECH_THRESHOLD: f32 = 0.6;
+const MAX_NGRAM: usize = 8;
+const MIN_REPEATS: usize = 4;
+// Pre-processing parameters behind the on/off switches
+const HIGH_PASS_HZ: f32 = 80.0;
+const NOISE_REDUCTION_STRENGTH: f32 = 1.5;
+const TARGET_RMS_DB: f32 = -20.0;
+const MAX_GAIN_DB: f32 = 30.0;
+// Cosine similarity below which a voice counts as a new speaker
+const SPEAKER_THRESHOLD: f32 = 0.5;
+const MAX_SPEAKERS: usize = 8;
 
 #[component]
 pub fn Settings() -> impl IntoView {
+    let state = expect_context::<AppState>();
+    let timestamps_enabled = state.timestamps_enabled;
+    let word_timestamps_enabled = state.word_timestamps_enabled;
+    let decoding_quality = state.decoding_quality;
+    let whisper_task = state.whisper_task;
+    let whisper_model = state.whisper_model;
+    let initial_prompt = state.initial_prompt;
+    let hotwords = state.hotwords;
+    let no_speech_check = state.no_speech_check;
+    let blocklist = state.blocklist;
+    let dsp_high_pass = state.dsp_high_pass;
+    let dsp_noise_reduction = state.dsp_noise_reduction;
+    let dsp_normalize = state.dsp_normalize;
+    let diarization_enabled = state.diarization_enabled;
+    let speaker_model_loaded = state.speaker_model_loaded;
+    let error_message = state.error_message;
+
+    let on_timestamps_change = move |ev: ev::Event| {
+        timestamps_enabled.set(event_target_checked(&ev));
+    };
+
+    let on_word_timestamps_change = move |ev: ev::Event| {
+        word_timestamps_enabled.set(event_target_checked(&ev));
+    };
+
+    let on_task_change = move |ev: ev::Event| {
+        whisper_task.set(if event_target_checked(&ev) {
+            WhisperTask::Translate
+        } else {
+            WhisperTask::Transcribe
+        });
+    };
+
+    let on_prompt_change = move |ev: ev::Event| {
+        initial_prompt.set(event_target_value(&ev));
+    };
+
+    // Comma-separated, e.g. "Anuvad, Priya, Kubernetes"
+    let on_hotwords_change = move |ev: ev::Event| {
+        let words = event_target_value(&ev)
+            .split(',')
+            .map(|w| w.trim().to_string())
+            .filter(|w| !w.is_empty())
+            .collect();
+        hotwords.set(words);
+    };
+
+    let on_no_speech_change = move |ev: ev::Event| {
+        no_speech_check.set(event_target_checked(&ev));
+    };
+
+    // One phrase per line
+    let on_blocklist_change = move |ev: ev::Event| {
+        let phrases = event_target_value(&ev)
+            .lines()
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        blocklist.set(phrases);
+    };
+
+    let on_high_pass_change = move |ev: ev::Event| {
+        dsp_high_pass.set(event_target_checked(&ev));
+    };
+
+    let on_noise_reduction_change = move |ev: ev::Event| {
+        dsp_noise_reduction.set(event_target_checked(&ev));
+    };
+
+    let on_normalize_change = move |ev: ev::Event| {
+        dsp_normalize.set(event_target_checked(&ev));
+    };
+
+    let on_diarization_change = move |ev: ev::Event| {
+        diarization_enabled.set(event_target_checked(&ev));
+    };
+
+    // WeSpeaker's ResNet34 converted to safetensors, as the README describes
+    let on_speaker_model_change = move |ev: ev::Event| {
+        let Some(file) = ev
+            .target()
+            .and_then(|t| t.dyn_into::<web_sys::HtmlInputElement>().ok())
+            .and_then(|input| input.files())
+            .and_then(|files| files.get(0))
+        else {
+            return;
+        };
+        speaker_model_loaded.set(false);
+        spawn_local(async move {
+            match JsFuture::from(file.array_buffer()).await {
+                Ok(buffer) => {
+                    bridge::send_to_whisper(&WorkerMessage::LoadSpeakerModel {
+                        model_bytes: js_sys::Uint8Array::new(&buffer).to_vec(),
+                    });
+                }
+                Err(e) => {
+                    error_message.set(Some(format!("Speaker model read failed: {e:?}")));
+                }
+            }
+        });
+    };
+
+    let on_quality_change = move |ev: ev::Event| {
+        if let Some(quality) = DecodingQuality::from_id(&event_target_value(&ev)) {
+            decoding_quality.set(quality);
+        }
+    };
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetTimestamps {
+            enabled: timestamps_enabled.get(),
+        });
+    });
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetWordTimestamps {
+            enabled: word_timestamps_enabled.get(),
+        });
+    });
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetTask {
+            task: whisper_task.get(),
+        });
+    });
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetDecoding {
+            decoding: decoding_quality.get().options(),
+        });
+    });
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetPrompt {
+            prompt: PromptOptions {
+                initial_prompt: initial_prompt.get(),
+                hotwords: hotwords.get(),
+                hotword_bias: HOTWORD_BIAS,
+            },
+        });
+    });
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetSafeguards {
+            safeguards: SafeguardOptions {
+                suppress_tokens: true,
+                suppress_blank: true,
+                no_speech_threshold: no_speech_check.get().then_some(NO_SPEECH_THRESHOLD),
+                max_ngram: MAX_NGRAM,
+                min_repeats: MIN_REPEATS,
+                blocklist: blocklist.get(),
+            },
+        });
+    });
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetDsp {
+            dsp: DspOptions {
+                high_pass: dsp_high_pass.get(),
+                high_pass_hz: HIGH_PASS_HZ,
+                noise_reduction: dsp_noise_reduction.get(),
+                noise_reduction_strength: NOISE_REDUCTION_STRENGTH,
+                normalize: dsp_normalize.get().then_some(Normalization::Rms {
+                    target_db: TARGET_RMS_DB,
+                }),
+                max_gain_db: MAX_GAIN_DB,
+            },
+        });
+    });
+
+    Effect::new(move |_| {
+        bridge::send_to_whisper(&WorkerMessage::SetDiarization {
+            diarization: DiarizationOptions {
+                enabled: diarization_enabled.get(),
+                threshold: SPEAKER_THRESHOLD,
+                max_speakers: MAX_SPEAKERS,
+            },
+        });
+    });
+
     view! {
         <div class="card">
             <h2 class="text-lg font-semibold mb-3">"Settings"</h2>
             <div class="space-y-3 text-sm text-gray-600 dark:text-gray-400">
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || timestamps_enabled.get()
+                        on:change=on_timestamps_change
+                    />
+                    "Segment timestamps"
+                </label>
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || word_timestamps_enabled.get()
+                        on:change=on_word_timestamps_change
+                    />
+                    "Word timestamps (slower, more precise timing)"
+                </label>
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || whisper_task.get() == WhisperTask::Translate
+                        disabled=move || whisper_model.get().english_only()
+                        on:change=on_task_change
+                    />
+                    "Translate speech to English with Whisper (no translator model needed)"
+                </label>
+                <label class="flex items-center gap-2">
+                    "Decoding"
+                    <select
+                        class="px-2 py-1 bg-gray-100 dark:bg-gray-800 border border-gray-300 dark:border-gray-700 rounded-lg text-sm"
+                        on:change=on_quality_change
+                    >
+                        {DecodingQuality::ALL.into_iter().map(|quality| {
+                            view! {
+                                <option
+                                    value=quality.id()
+                                    selected=move || decoding_quality.get() == quality
+                                >
+                                    {quality.label()}
+                                </option>
+                            }
+                        }).collect::<Vec<_>>()}
+                    </select>
+                </label>
+                <label class="block">
+                    "Vocabulary prompt"
+                    <textarea
+                        class="w-full mt-1 px-3 py-2 bg-gray-100 dark:bg-gray-800 border border-gray-300 dark:border-gray-700 rounded-lg text-sm"
+                        rows="2"
+                        placeholder="Context or glossary, e.g. Meeting with Priya about Anuvad and Kubernetes."
+                        prop:value=move || initial_prompt.get()
+                        on:change=on_prompt_change
+                    ></textarea>
+                </label>
+                <label class="block">
+                    "Hotwords"
+                    <input
+                        type="text"
+                        class="w-full mt-1 px-3 py-2 bg-gray-100 dark:bg-gray-800 border border-gray-300 dark:border-gray-700 rounded-lg text-sm"
+                        placeholder="Comma-separated, e.g. Anuvad, Priya"
+                        prop:value=move || hotwords.get().join(", ")
+                        on:change=on_hotwords_change
+                    />
+                </label>
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || no_speech_check.get()
+                        on:change=on_no_speech_change
+                    />
+                    "Drop text heard over silence"
+                </label>
+                <label class="block">
+                    "Phrases to drop"
+                    <textarea
+                        class="w-full mt-1 px-3 py-2 bg-gray-100 dark:bg-gray-800 border border-gray-300 dark:border-gray-700 rounded-lg text-sm"
+                        rows="3"
+                        placeholder="One per line, e.g. thanks for watching"
+                        prop:value=move || blocklist.get().join("\n")
+                        on:change=on_blocklist_change
+                    ></textarea>
+                </label>
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || dsp_high_pass.get()
+                        on:change=on_high_pass_change
+                    />
+                    "Filter out hum and rumble"
+                </label>
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || dsp_noise_reduction.get()
+                        on:change=on_noise_reduction_change
+                    />
+                    "Reduce background noise (fans, air conditioning)"
+                </label>
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || dsp_normalize.get()
+                        on:change=on_normalize_change
+                    />
+                    "Even out quiet and loud audio"
+                </label>
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || diarization_enabled.get()
+                        disabled=move || !speaker_model_loaded.get()
+                        on:change=on_diarization_change
+                    />
+                    "Identify speakers"
+                </label>
+                <label class="block">
+                    "Speaker model"
+                    <input
+                        type="file"
+                        accept=".safetensors"
+                        class="block mt-1 text-xs"
+                        on:change=on_speaker_model_change
+                    />
+                    <span class="block mt-1 text-xs text-gray-500 dark:text-gray-400">
+                        "WeSpeaker ResNet34 (pyannote/wespeaker-voxceleb-resnet34-LM) as safetensors"
+                    </span>
+                </label>
                 <p>"Backend: CPU (WASM SIMD)"</p>
                 <p>"WebGPU support coming in v2."</p>
             </div>
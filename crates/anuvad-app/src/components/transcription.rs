@@ -1,14 +1,45 @@
 use leptos::prelude::*;
+use std::collections::HashMap;
 
 use crate::state::{AppState, RecordingState};
+use crate::workers::bridge::{Channel, Safeguard};
+
+// Words Whisper was less sure of than this are shaded for review
+const LOW_CONFIDENCE: f32 = 0.5;
+// The shared stream, then each separately transcribed side
+const INPUTS: [Option<Channel>; 3] = [None, Some(Channel::Me), Some(Channel::Remote)];
 
 #[component]
 pub fn TranscriptionPanel() -> impl IntoView {
     let state = expect_context::<AppState>();
 
     let transcription_text = state.transcription_text;
+    let partial_text = state.partial_text;
+    let transcript_segments = state.transcript_segments;
+    let transcript_words = state.transcript_words;
     let detected_language = state.detected_language;
+    let detected_language_probability = state.detected_language_probability;
     let recording_state = state.recording_state;
+    let speech_active = state.speech_active;
+    let safeguards = state.safeguards;
+    let speaker_names = state.speaker_names;
+
+    let rename_speaker = move |speaker: usize| {
+        let current = speaker_label(&speaker_names.get_untracked(), speaker);
+        let name = web_sys::window()
+            .and_then(|w| w.prompt_with_message_and_default("Speaker name", &current).ok())
+            .flatten();
+        if let Some(name) = name {
+            let name = name.trim().to_string();
+            speaker_names.update(|names| {
+                if name.is_empty() {
+                    names.remove(&speaker);
+                } else {
+                    names.insert(speaker, name);
+                }
+            });
+        }
+    };
 
     let copy_text = move |_| {
         let text = transcription_text.get_untracked();
@@ -25,11 +56,47 @@ pub fn TranscriptionPanel() -> impl IntoView {
             <div class="flex items-center justify-between">
                 <h2 class="text-lg font-semibold">"Transcription"</h2>
                 <div class="flex items-center gap-2">
+                    {move || {
+                        (recording_state.get() == RecordingState::Recording).then(|| {
+                            let mut speech = speech_active.get();
+                            if speech.is_empty() {
+                                speech.insert(None, false);
+                            }
+                            INPUTS.into_iter().filter_map(|input| {
+                                let speaking = *speech.get(&input)?;
+                                let state = if speaking { "Speech" } else { "Silence" };
+                                let label = match input {
+                                    Some(channel) => format!("{}: {state}", channel.label()),
+                                    None => state.to_string(),
+                                };
+                                Some(if speaking {
+                                    view! { <span class="badge-ready">{label}</span> }.into_any()
+                                } else {
+                                    view! { <span class="badge bg-gray-100 dark:bg-gray-800 text-gray-600 dark:text-gray-400">{label}</span> }.into_any()
+                                })
+                            }).collect::<Vec<_>>()
+                        })
+                    }}
+                    {move || {
+                        let applied = safeguards.get();
+                        (!applied.is_empty()).then(|| {
+                            let details = applied.iter().map(safeguard_label).collect::<Vec<_>>().join("\n");
+                            view! {
+                                <span class="badge-loading" title=details>
+                                    "Suppressed"
+                                </span>
+                            }
+                        })
+                    }}
                     {move || {
                         detected_language.get().map(|lang| {
+                            let label = match detected_language_probability.get() {
+                                Some(p) => format!("Detected: {lang} ({:.0}%)", p * 100.0),
+                                None => format!("Detected: {lang}"),
+                            };
                             view! {
                                 <span class="badge-ready">
-                                    {format!("Detected: {lang}")}
+                                    {label}
                                 </span>
                             }
                         })
@@ -47,7 +114,8 @@ pub fn TranscriptionPanel() -> impl IntoView {
             <div class="text-panel">
                 {move || {
                     let text = transcription_text.get();
-                    if text.is_empty() {
+                    let partial = partial_text.get();
+                    if text.is_empty() && partial.values().all(String::is_empty) {
                         view! {
                             <span class="text-gray-400 dark:text-gray-600 italic">
                                 "Transcription will appear here\u{2026}"
@@ -55,8 +123,81 @@ pub fn TranscriptionPanel() -> impl IntoView {
                         }.into_any()
                     } else {
                         let is_recording = recording_state.get() == RecordingState::Recording;
+                        let segments = transcript_segments.get();
+                        let words = transcript_words.get();
+                        let body = if segments.is_empty() && words.is_empty() {
+                            view! { <span>{text}</span> }.into_any()
+                        } else if segments.is_empty() {
+                            words.into_iter().map(|word| {
+                                let class = if word.probability < LOW_CONFIDENCE {
+                                    "bg-amber-100 dark:bg-amber-900/40 rounded"
+                                } else {
+                                    ""
+                                };
+                                view! {
+                                    <span
+                                        class=class
+                                        title=format!(
+                                            "{} \u{00b7} {:.0}% confident",
+                                            format_time(word.start),
+                                            word.probability * 100.0,
+                                        )
+                                    >
+                                        {word.text}
+                                    </span>
+                                }
+                            }).collect::<Vec<_>>().into_any()
+                        } else {
+                            let names = speaker_names.get();
+                            let mut previous = (None, None);
+                            segments.into_iter().map(|seg| {
+                                // Name the channel and speaker at each change of turn
+                                let turn = (seg.channel, seg.speaker);
+                                let heading = (turn != previous && turn != (None, None)).then(|| {
+                                    let speaker = seg.speaker.map(|speaker| view! {
+                                        <button
+                                            class="font-semibold text-indigo-600 dark:text-indigo-400 hover:underline"
+                                            title="Rename speaker"
+                                            on:click=move |_| rename_speaker(speaker)
+                                        >
+                                            {speaker_label(&names, speaker)}
+                                        </button>
+                                    });
+                                    view! {
+                                        <div class="flex items-center gap-2 mt-2 text-xs">
+                                            {seg.channel.map(|channel| view! {
+                                                <span class="badge-ready">{channel.label()}</span>
+                                            })}
+                                            {speaker}
+                                        </div>
+                                    }
+                                });
+                                previous = turn;
+                                view! {
+                                    {heading}
+                                    <p>
+                                        <span class="text-xs text-gray-400 dark:text-gray-500 mr-2">
+                                            {format_time(seg.start)}
+                                        </span>
+                                        {seg.text}
+                                    </p>
+                                }
+                            }).collect::<Vec<_>>().into_any()
+                        };
+                        let partial = INPUTS.into_iter().filter_map(|input| {
+                            let text = partial.get(&input).filter(|t| !t.is_empty())?.clone();
+                            Some(view! {
+                                <span class="text-gray-400 dark:text-gray-500 italic">
+                                    {input.map(|channel| view! {
+                                        <span class="badge-ready not-italic ml-1">{channel.label()}</span>
+                                    })}
+                                    {format!(" {text}")}
+                                </span>
+                            })
+                        }).collect::<Vec<_>>();
                         view! {
-                            <span>{text}</span>
+                            {body}
+                            {partial}
                             {if is_recording {
                                 Some(view! { <span class="animate-pulse text-indigo-500">{"\u{2588}"}</span> })
                             } else {
@@ -69,3 +210,25 @@ pub fn TranscriptionPanel() -> impl IntoView {
         </div>
     }
 }
+
+fn safeguard_label(safeguard: &Safeguard) -> String {
+    match safeguard {
+        Safeguard::NoSpeech { probability } => {
+            format!("Dropped as silence ({:.0}% no speech)", probability * 100.0)
+        }
+        Safeguard::RepetitionCut { removed } => format!("Cut repetition: {removed}"),
+        Safeguard::Blocklisted { text } => format!("Dropped stock phrase: {text}"),
+    }
+}
+
+fn speaker_label(names: &HashMap<usize, String>, speaker: usize) -> String {
+    names
+        .get(&speaker)
+        .cloned()
+        .unwrap_or_else(|| format!("Speaker {}", speaker + 1))
+}
+
+fn format_time(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    format!("{:02}:{:02}", total / 60, total % 60)
+}
@@ -2,7 +2,7 @@ use leptos::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 
 use crate::state::{AppState, ModelStatus, RecordingState};
-use crate::workers::bridge;
+use crate::workers::bridge::{self, WhisperTask};
 
 #[component]
 pub fn TranslationPanel() -> impl IntoView {
@@ -13,6 +13,7 @@ pub fn TranslationPanel() -> impl IntoView {
     let translation_text = state.translation_text;
     let recording_state = state.recording_state;
     let target_language = state.target_language;
+    let whisper_task = state.whisper_task;
 
     let translate = move |_| {
         spawn_local(async move {
@@ -67,7 +68,16 @@ pub fn TranslationPanel() -> impl IntoView {
             <div class="text-panel">
                 {move || {
                     let text = translation_text.get();
-                    if text.is_empty() {
+                    if text.is_empty()
+                        && whisper_task.get() == WhisperTask::Translate
+                        && target_language.get() == "en"
+                    {
+                        view! {
+                            <span class="text-gray-400 dark:text-gray-600 italic">
+                                "Whisper is translating to English directly; the transcript is the translation."
+                            </span>
+                        }.into_any()
+                    } else if text.is_empty() {
                         view! {
                             <span class="text-gray-400 dark:text-gray-600 italic">
                                 "Translation will appear here\u{2026}"
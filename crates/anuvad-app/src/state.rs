@@ -1,5 +1,11 @@
 use leptos::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::workers::bridge::{
+    Channel, DecodingOptions, DecodingStrategy, LoadReport, Safeguard, TemperatureFallback,
+    WeightPrecision, WhisperTask,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioSource {
@@ -46,41 +52,332 @@ pub enum RecordingState {
     Processing,
 }
 
+/// A Whisper checkpoint on the Hugging Face Hub.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhisperModel {
+    Tiny,
+    TinyEn,
+    Base,
+    BaseEn,
+    Small,
+    SmallEn,
+    Medium,
+    MediumEn,
+    LargeV3,
+    LargeV3Turbo,
+    DistilSmallEn,
+    DistilLargeV3,
+}
+
+impl WhisperModel {
+    pub const ALL: [Self; 12] = [
+        Self::Tiny,
+        Self::TinyEn,
+        Self::Base,
+        Self::BaseEn,
+        Self::Small,
+        Self::SmallEn,
+        Self::Medium,
+        Self::MediumEn,
+        Self::LargeV3,
+        Self::LargeV3Turbo,
+        Self::DistilSmallEn,
+        Self::DistilLargeV3,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Tiny => "Whisper Tiny",
+            Self::TinyEn => "Whisper Tiny (English)",
+            Self::Base => "Whisper Base",
+            Self::BaseEn => "Whisper Base (English)",
+            Self::Small => "Whisper Small",
+            Self::SmallEn => "Whisper Small (English)",
+            Self::Medium => "Whisper Medium",
+            Self::MediumEn => "Whisper Medium (English)",
+            Self::LargeV3 => "Whisper Large v3",
+            Self::LargeV3Turbo => "Whisper Large v3 Turbo",
+            Self::DistilSmallEn => "Distil-Whisper Small (English)",
+            Self::DistilLargeV3 => "Distil-Whisper Large v3",
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        self.repo().split('/').nth(1).unwrap_or_default()
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.id() == id)
+    }
+
+    /// Hugging Face repository holding `model.safetensors`, `tokenizer.json`
+    /// and `config.json`.
+    pub fn repo(&self) -> &'static str {
+        match self {
+            Self::Tiny => "openai/whisper-tiny",
+            Self::TinyEn => "openai/whisper-tiny.en",
+            Self::Base => "openai/whisper-base",
+            Self::BaseEn => "openai/whisper-base.en",
+            Self::Small => "openai/whisper-small",
+            Self::SmallEn => "openai/whisper-small.en",
+            Self::Medium => "openai/whisper-medium",
+            Self::MediumEn => "openai/whisper-medium.en",
+            Self::LargeV3 => "openai/whisper-large-v3",
+            Self::LargeV3Turbo => "openai/whisper-large-v3-turbo",
+            Self::DistilSmallEn => "distil-whisper/distil-small.en",
+            Self::DistilLargeV3 => "distil-whisper/distil-large-v3",
+        }
+    }
+
+    /// Approximate download size.
+    pub fn size(&self) -> &'static str {
+        match self {
+            Self::Tiny | Self::TinyEn => "~150 MB",
+            Self::Base | Self::BaseEn => "~290 MB",
+            Self::Small | Self::SmallEn => "~970 MB",
+            Self::Medium | Self::MediumEn => "~3.1 GB",
+            Self::LargeV3 => "~3.1 GB",
+            Self::LargeV3Turbo => "~1.6 GB",
+            Self::DistilSmallEn => "~330 MB",
+            Self::DistilLargeV3 => "~1.5 GB",
+        }
+    }
+
+    /// English-only models transcribe English and cannot translate.
+    pub fn english_only(&self) -> bool {
+        self.id().ends_with(".en")
+    }
+
+    /// Models whose F32 weights would not fit the 4 GiB wasm32 heap, so
+    /// they are only loaded at half precision. Medium (769M parameters)
+    /// takes about 1.5 GB at F16; Large v3 (1.55B) about 3.1 GB, which
+    /// leaves little room beside the download.
+    pub fn needs_half_precision(&self) -> bool {
+        matches!(
+            self,
+            Self::Medium
+                | Self::MediumEn
+                | Self::LargeV3
+                | Self::LargeV3Turbo
+                | Self::DistilLargeV3
+        )
+    }
+}
+
+// Mirrors `anuvad_whisper::safeguards::DEFAULT_BLOCKLIST`
+const DEFAULT_BLOCKLIST: &[&str] = &[
+    "thank you for watching",
+    "thanks for watching",
+    "please subscribe",
+    "like and subscribe",
+    "subtitles by the amara org community",
+    "untertitel der amara org community",
+    "ご視聴ありがとうございました",
+    "字幕由amara org社区提供",
+];
+
+/// Speed/accuracy trade-off for Whisper decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodingQuality {
+    Fast,
+    Balanced,
+    Accurate,
+}
+
+impl DecodingQuality {
+    pub const ALL: [Self; 3] = [Self::Fast, Self::Balanced, Self::Accurate];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Fast => "Fast (greedy)",
+            Self::Balanced => "Balanced (greedy + fallback)",
+            Self::Accurate => "Accurate (beam search + fallback)",
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Fast => "fast",
+            Self::Balanced => "balanced",
+            Self::Accurate => "accurate",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|q| q.id() == id)
+    }
+
+    pub fn options(&self) -> DecodingOptions {
+        // Same ladder and thresholds as the reference implementation
+        let fallback = TemperatureFallback {
+            temperatures: vec![0.0, 0.2, 0.4, 0.6, 0.8, 1.0],
+            compression_ratio_threshold: Some(2.4),
+            logprob_threshold: Some(-1.0),
+        };
+        match self {
+            Self::Fast => DecodingOptions {
+                strategy: DecodingStrategy::Greedy,
+                fallback: None,
+            },
+            Self::Balanced => DecodingOptions {
+                strategy: DecodingStrategy::Greedy,
+                fallback: Some(fallback),
+            },
+            Self::Accurate => DecodingOptions {
+                strategy: DecodingStrategy::BeamSearch {
+                    beam_size: 5,
+                    patience: 1.0,
+                },
+                fallback: Some(fallback),
+            },
+        }
+    }
+}
+
+/// A transcribed word and how confident Whisper was in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptWord {
+    /// With its leading space, if it has one.
+    pub text: String,
+    pub probability: f32,
+    #[serde(default)]
+    pub start: f64,
+    #[serde(default)]
+    pub end: f64,
+}
+
+/// A timed piece of transcript, in seconds since recording started.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    /// Index of the speaker, when speakers are being identified.
+    #[serde(default)]
+    pub speaker: Option<usize>,
+    /// Input it was heard on, when "Both" keeps the inputs apart.
+    #[serde(default)]
+    pub channel: Option<Channel>,
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub whisper_status: RwSignal<ModelStatus>,
+    pub whisper_model: RwSignal<WhisperModel>,
+    /// How Whisper's safetensors weights are stored once loaded.
+    pub whisper_precision: RwSignal<WeightPrecision>,
     pub translator_status: RwSignal<ModelStatus>,
     pub whisper_progress: RwSignal<f64>,
     pub translator_progress: RwSignal<f64>,
+    /// Load time and memory of the Whisper model the worker last loaded.
+    pub whisper_load_report: RwSignal<Option<LoadReport>>,
     pub recording_state: RwSignal<RecordingState>,
+    /// Committed transcript; only ever appended to while streaming.
     pub transcription_text: RwSignal<String>,
+    /// Tentative text after the committed transcript of each input (`None`
+    /// for the shared stream), replaced on each update.
+    pub partial_text: RwSignal<HashMap<Option<Channel>, String>>,
+    pub transcript_segments: RwSignal<Vec<TranscriptSegment>>,
+    /// Committed words with their confidence, for shading doubtful ones.
+    pub transcript_words: RwSignal<Vec<TranscriptWord>>,
+    /// Output Whisper suppressed in its latest update.
+    pub safeguards: RwSignal<Vec<Safeguard>>,
+    /// Share of captured samples clipped since the previous update.
+    pub input_clipping: RwSignal<f32>,
+    /// Pre-processing stages run on captured audio before Whisper.
+    pub dsp_high_pass: RwSignal<bool>,
+    pub dsp_noise_reduction: RwSignal<bool>,
+    pub dsp_normalize: RwSignal<bool>,
+    /// Label segments with who is speaking; needs a speaker model loaded.
+    pub diarization_enabled: RwSignal<bool>,
+    pub speaker_model_loaded: RwSignal<bool>,
+    /// Names given to speakers in place of "Speaker N".
+    pub speaker_names: RwSignal<HashMap<usize, String>>,
+    pub timestamps_enabled: RwSignal<bool>,
+    /// Time words from cross-attention instead of spreading them evenly.
+    pub word_timestamps_enabled: RwSignal<bool>,
+    /// `Translate` has Whisper write English itself, without the translator.
+    pub whisper_task: RwSignal<WhisperTask>,
+    pub decoding_quality: RwSignal<DecodingQuality>,
+    /// Glossary or context text Whisper is primed with.
+    pub initial_prompt: RwSignal<String>,
+    /// Names and terms Whisper is nudged towards.
+    pub hotwords: RwSignal<Vec<String>>,
+    /// Drop output Whisper thinks is more likely silence than speech.
+    pub no_speech_check: RwSignal<bool>,
+    /// Stock phrases Whisper invents over silence; segments of only these
+    /// are dropped.
+    pub blocklist: RwSignal<Vec<String>>,
     pub translation_text: RwSignal<String>,
     pub source_language: RwSignal<String>,
+    pub allowed_languages: RwSignal<Vec<String>>,
     pub target_language: RwSignal<String>,
     pub detected_language: RwSignal<Option<String>>,
+    pub detected_language_probability: RwSignal<Option<f32>>,
     pub audio_level: RwSignal<f64>,
+    /// Transcription requests waiting in the whisper worker.
+    pub whisper_queue_depth: RwSignal<usize>,
+    /// Whether the voice activity detector currently hears speech, per
+    /// input.
+    pub speech_active: RwSignal<HashMap<Option<Channel>, bool>>,
     pub error_message: RwSignal<Option<String>>,
     pub recording_duration: RwSignal<f64>,
     pub audio_source: RwSignal<AudioSource>,
+    /// With `AudioSource::Both`, transcribe the microphone and the tab or
+    /// system audio separately instead of mixing them.
+    pub separate_channels: RwSignal<bool>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
             whisper_status: RwSignal::new(ModelStatus::NotDownloaded),
+            whisper_model: RwSignal::new(WhisperModel::Small),
+            whisper_precision: RwSignal::new(WeightPrecision::F32),
             translator_status: RwSignal::new(ModelStatus::NotDownloaded),
             whisper_progress: RwSignal::new(0.0),
             translator_progress: RwSignal::new(0.0),
+            whisper_load_report: RwSignal::new(None),
             recording_state: RwSignal::new(RecordingState::Idle),
             transcription_text: RwSignal::new(String::new()),
+            partial_text: RwSignal::new(HashMap::new()),
+            transcript_segments: RwSignal::new(Vec::new()),
+            transcript_words: RwSignal::new(Vec::new()),
+            safeguards: RwSignal::new(Vec::new()),
+            input_clipping: RwSignal::new(0.0),
+            dsp_high_pass: RwSignal::new(true),
+            dsp_noise_reduction: RwSignal::new(false),
+            dsp_normalize: RwSignal::new(true),
+            diarization_enabled: RwSignal::new(false),
+            speaker_model_loaded: RwSignal::new(false),
+            speaker_names: RwSignal::new(HashMap::new()),
+            timestamps_enabled: RwSignal::new(false),
+            word_timestamps_enabled: RwSignal::new(false),
+            whisper_task: RwSignal::new(WhisperTask::Transcribe),
+            decoding_quality: RwSignal::new(DecodingQuality::Fast),
+            initial_prompt: RwSignal::new(String::new()),
+            hotwords: RwSignal::new(Vec::new()),
+            no_speech_check: RwSignal::new(true),
+            blocklist: RwSignal::new(DEFAULT_BLOCKLIST.iter().map(|p| p.to_string()).collect()),
             translation_text: RwSignal::new(String::new()),
             source_language: RwSignal::new("auto".to_string()),
+            allowed_languages: RwSignal::new(Vec::new()),
             target_language: RwSignal::new("en".to_string()),
             detected_language: RwSignal::new(None),
+            detected_language_probability: RwSignal::new(None),
             audio_level: RwSignal::new(0.0),
+            whisper_queue_depth: RwSignal::new(0),
+            speech_active: RwSignal::new(HashMap::new()),
             error_message: RwSignal::new(None),
             recording_duration: RwSignal::new(0.0),
             audio_source: RwSignal::new(AudioSource::Microphone),
+            separate_channels: RwSignal::new(false),
         }
     }
 }
@@ -11,9 +11,9 @@ use crate::state::AppState;
 use crate::workers::bridge::{self, WorkerMessage};
 
 thread_local! {
-    static AUDIO_CTX: RefCell<Option<AudioContext>> = RefCell::new(None);
-    static SCRIPT_PROCESSOR: RefCell<Option<ScriptProcessorNode>> = RefCell::new(None);
-    static MEDIA_STREAM: RefCell<Option<MediaStream>> = RefCell::new(None);
+    static AUDIO_CTX: RefCell<Option<AudioContext>> = const { RefCell::new(None) };
+    static SCRIPT_PROCESSOR: RefCell<Option<ScriptProcessorNode>> = const { RefCell::new(None) };
+    static MEDIA_STREAM: RefCell<Option<MediaStream>> = const { RefCell::new(None) };
 }
 
 pub async fn start_recording() -> Result<(), String> {
@@ -35,7 +35,8 @@ pub async fn start_recording() -> Result<(), String> {
 
     let stream: MediaStream = stream_js.dyn_into().map_err(|_| "Not a MediaStream")?;
 
-    // Create AudioContext at 16kHz
+    // Ask for 16kHz; a context running at another rate is resampled by the
+    // worker
     let opts = AudioContextOptions::new();
     opts.set_sample_rate(16000.0);
     let ctx = AudioContext::new_with_context_options(&opts)
@@ -64,6 +65,8 @@ pub async fn start_recording() -> Result<(), String> {
         // Send PCM data to whisper worker
         let msg = WorkerMessage::Transcribe {
             audio: channel_data.to_vec(),
+            sample_rate: input_buffer.sample_rate() as u32,
+            channel: None,
         };
         bridge::send_to_whisper(&msg);
 
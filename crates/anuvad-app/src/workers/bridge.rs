@@ -1,11 +1,11 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{Worker, WorkerOptions, WorkerType, MessageEvent};
-use leptos::prelude::{Set, Update};
+use leptos::prelude::{GetUntracked, Set, Update};
 use serde::{Serialize, Deserialize};
 use std::cell::RefCell;
 
-use crate::state::AppState;
+use crate::state::{AppState, ModelStatus, TranscriptSegment, TranscriptWord};
 
 pub fn worker_script_url(filename: &str) -> String {
     #[cfg(feature = "extension")]
@@ -30,17 +30,212 @@ pub fn worker_script_url(filename: &str) -> String {
     format!("./{filename}")
 }
 
+// Mirrors `anuvad_whisper::decoder::Task` for the SetTask message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WhisperTask {
+    Transcribe,
+    Translate,
+}
+
+// Mirrors `anuvad_whisper::decoding` for the SetDecoding message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DecodingStrategy {
+    Greedy,
+    BeamSearch { beam_size: usize, patience: f32 },
+    Sampling { temperature: f64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TemperatureFallback {
+    pub temperatures: Vec<f64>,
+    pub compression_ratio_threshold: Option<f64>,
+    pub logprob_threshold: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DecodingOptions {
+    pub strategy: DecodingStrategy,
+    pub fallback: Option<TemperatureFallback>,
+}
+
+// Mirrors `anuvad_whisper::decoder::PromptOptions` for the SetPrompt message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PromptOptions {
+    pub initial_prompt: String,
+    pub hotwords: Vec<String>,
+    pub hotword_bias: f32,
+}
+
+// Mirrors `anuvad_whisper::safeguards::SafeguardOptions` for the
+// SetSafeguards message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SafeguardOptions {
+    pub suppress_tokens: bool,
+    pub suppress_blank: bool,
+    pub no_speech_threshold: Option<f32>,
+    pub max_ngram: usize,
+    pub min_repeats: usize,
+    pub blocklist: Vec<String>,
+}
+
+// Mirrors `anuvad_whisper::safeguards::Safeguard` on TranscriptionResult
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Safeguard {
+    NoSpeech { probability: f32 },
+    RepetitionCut { removed: String },
+    Blocklisted { text: String },
+}
+
+// Mirrors `anuvad_whisper::audio::dsp` for the SetDsp message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Normalization {
+    Rms { target_db: f32 },
+    Peak { target_db: f32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DspOptions {
+    pub high_pass: bool,
+    pub high_pass_hz: f32,
+    pub noise_reduction: bool,
+    pub noise_reduction_strength: f32,
+    pub normalize: Option<Normalization>,
+    pub max_gain_db: f32,
+}
+
+// Mirrors `anuvad_whisper::speaker::DiarizationOptions` for the
+// SetDiarization message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiarizationOptions {
+    pub enabled: bool,
+    pub threshold: f32,
+    pub max_speakers: usize,
+}
+
+// Mirrors `anuvad_whisper::channels::Channel`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Me,
+    Remote,
+}
+
+impl Channel {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Me => "Me",
+            Self::Remote => "Remote",
+        }
+    }
+}
+
+// Mirrors `anuvad_whisper::decoder::WeightPrecision`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightPrecision {
+    F32,
+    F16,
+    Bf16,
+}
+
+impl WeightPrecision {
+    pub const ALL: [Self; 3] = [Self::F32, Self::F16, Self::Bf16];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::F32 => "F32",
+            Self::F16 => "F16",
+            Self::Bf16 => "BF16",
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::F32 => "f32",
+            Self::F16 => "f16",
+            Self::Bf16 => "bf16",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.id() == id)
+    }
+}
+
+// Mirrors `anuvad_whisper::LoadReport` on ModelLoaded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoadReport {
+    pub precision: WeightPrecision,
+    pub quantized: bool,
+    pub model_bytes: usize,
+    pub load_ms: f64,
+    pub peak_memory_bytes: Option<usize>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum WorkerMessage {
     // To whisper worker
-    LoadModel { data: Vec<u8> },
-    Transcribe { audio: Vec<f32> },
+    /// The weights go alongside as `model_bytes`; see `load_whisper_model`.
+    LoadModel {
+        tokenizer_json: String,
+        config_json: String,
+        generation_config_json: Option<String>,
+        precision: WeightPrecision,
+    },
+    Transcribe { audio: Vec<f32>, sample_rate: u32, channel: Option<Channel> },
+    SetLanguage { language: String, allowed_languages: Vec<String> },
+    SetTask { task: WhisperTask },
+    SetTimestamps { enabled: bool },
+    SetWordTimestamps { enabled: bool },
+    SetDecoding { decoding: DecodingOptions },
+    SetPrompt { prompt: PromptOptions },
+    SetSafeguards { safeguards: SafeguardOptions },
+    SetDsp { dsp: DspOptions },
+    LoadSpeakerModel { model_bytes: Vec<u8> },
+    SetDiarization { diarization: DiarizationOptions },
+    Cancel,
+    Flush,
+    Reset { channel: Option<Channel> },
 
     // From whisper worker
-    ModelLoaded,
-    TranscriptionResult { text: String, language: Option<String> },
-    TranscriptionPartial { text: String },
+    ModelLoaded {
+        #[serde(default)]
+        report: Option<LoadReport>,
+    },
+    SpeakerModelLoaded,
+    TranscriptionResult {
+        text: String,
+        language: Option<String>,
+        #[serde(default)]
+        language_probability: Option<f32>,
+        #[serde(default)]
+        segments: Vec<TranscriptSegment>,
+        #[serde(default)]
+        words: Vec<TranscriptWord>,
+        #[serde(default)]
+        safeguards: Vec<Safeguard>,
+        #[serde(default)]
+        clipping: f32,
+        #[serde(default)]
+        channel: Option<Channel>,
+    },
+    TranscriptionPartial {
+        text: String,
+        #[serde(default)]
+        channel: Option<Channel>,
+    },
+    VoiceActivity {
+        speaking: bool,
+        time: f64,
+        #[serde(default)]
+        channel: Option<Channel>,
+    },
+    QueueDepth { depth: usize },
 
     // To translator worker
     LoadTranslatorModel { data: Vec<u8> },
@@ -57,8 +252,10 @@ pub enum WorkerMessage {
 }
 
 thread_local! {
-    static WHISPER_WORKER: RefCell<Option<Worker>> = RefCell::new(None);
-    static TRANSLATOR_WORKER: RefCell<Option<Worker>> = RefCell::new(None);
+    static WHISPER_WORKER: RefCell<Option<Worker>> = const { RefCell::new(None) };
+    static TRANSLATOR_WORKER: RefCell<Option<Worker>> = const { RefCell::new(None) };
+    // Shared with the whisper worker when the page is cross-origin isolated
+    static WHISPER_CANCEL_FLAG: RefCell<Option<js_sys::Int32Array>> = const { RefCell::new(None) };
 }
 
 pub fn init_whisper_worker() -> Result<Worker, JsValue> {
@@ -96,6 +293,61 @@ pub fn send_to_whisper(msg: &WorkerMessage) {
     });
 }
 
+/// Sends `LoadModel` with the weights attached as a transferred
+/// `Uint8Array`, which serde would turn into an array of numbers.
+pub fn load_whisper_model(
+    model_bytes: js_sys::Uint8Array,
+    tokenizer_json: String,
+    config_json: String,
+    generation_config_json: Option<String>,
+    precision: WeightPrecision,
+) -> Result<(), String> {
+    let msg = WorkerMessage::LoadModel {
+        tokenizer_json,
+        config_json,
+        generation_config_json,
+        precision,
+    };
+    let val = serde_wasm_bindgen::to_value(&msg).map_err(|e| format!("{e}"))?;
+    js_sys::Reflect::set(&val, &"model_bytes".into(), &model_bytes)
+        .map_err(|e| format!("{e:?}"))?;
+    WHISPER_WORKER.with(|w| {
+        let worker = w.borrow();
+        let worker = worker.as_ref().ok_or("Whisper worker not started")?;
+        worker
+            .post_message_with_transfer(&val, &js_sys::Array::of1(&model_bytes.buffer()))
+            .map_err(|e| format!("{e:?}"))
+    })
+}
+
+/// Starts every whisper stream over, so a new recording's timeline begins
+/// at 0 with nothing carried over from the last one.
+pub fn reset_whisper_streams() {
+    for channel in [None, Some(Channel::Me), Some(Channel::Remote)] {
+        send_to_whisper(&WorkerMessage::Reset { channel });
+    }
+}
+
+/// Aborts the whisper worker's running decode, when it shares a cancel flag,
+/// and drops its queued ones.
+pub fn cancel_whisper() {
+    WHISPER_CANCEL_FLAG.with(|f| {
+        if let Some(flag) = f.borrow().as_ref() {
+            let _ = js_sys::Atomics::store(flag, 0, 1);
+        }
+    });
+    send_to_whisper(&WorkerMessage::Cancel);
+}
+
+// The worker's CancelFlag message carries shared memory, which serde can't
+fn cancel_flag(data: &JsValue) -> Option<js_sys::Int32Array> {
+    let kind = js_sys::Reflect::get(data, &"type".into()).ok()?.as_string()?;
+    if kind != "CancelFlag" {
+        return None;
+    }
+    js_sys::Reflect::get(data, &"flag".into()).ok()?.dyn_into().ok()
+}
+
 pub fn send_to_translator(msg: &WorkerMessage) {
     TRANSLATOR_WORKER.with(|w| {
         if let Some(worker) = w.borrow().as_ref() {
@@ -119,18 +371,79 @@ pub fn setup_whisper_listener(state: AppState) {
             let state = state.clone();
             let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
                 let data = event.data();
+                if let Some(flag) = cancel_flag(&data) {
+                    WHISPER_CANCEL_FLAG.with(|f| *f.borrow_mut() = Some(flag));
+                    return;
+                }
                 if let Ok(msg) = serde_wasm_bindgen::from_value::<WorkerMessage>(data) {
                     match msg {
-                        WorkerMessage::TranscriptionResult { text, language } => {
-                            state.transcription_text.set(text);
+                        WorkerMessage::TranscriptionResult {
+                            text,
+                            language,
+                            language_probability,
+                            segments,
+                            words,
+                            safeguards,
+                            clipping,
+                            channel,
+                        } => {
+                            // Separate channels arrive out of order; keep
+                            // the conversation in time order
+                            state.transcript_segments.update(|s| {
+                                s.extend(segments);
+                                s.sort_by(|a, b| a.start.total_cmp(&b.start));
+                            });
+                            if channel.is_some() {
+                                let segments = state.transcript_segments.get_untracked();
+                                state.transcription_text.set(
+                                    segments
+                                        .iter()
+                                        .map(|s| s.text.as_str())
+                                        .filter(|t| !t.is_empty())
+                                        .collect::<Vec<_>>()
+                                        .join(" "),
+                                );
+                            } else if !text.is_empty() {
+                                state.transcription_text.update(|t| {
+                                    if !t.is_empty() {
+                                        t.push(' ');
+                                    }
+                                    t.push_str(&text);
+                                });
+                            }
+                            state.transcript_words.update(|w| w.extend(words));
+                            state.safeguards.set(safeguards);
+                            state.input_clipping.set(clipping);
                             if let Some(lang) = language {
                                 state.detected_language.set(Some(lang));
+                                state.detected_language_probability.set(language_probability);
                             }
                         }
-                        WorkerMessage::TranscriptionPartial { text } => {
-                            state.transcription_text.set(text);
+                        WorkerMessage::ModelLoaded { report } => {
+                            state.whisper_load_report.set(report);
+                            state.whisper_status.set(ModelStatus::Ready);
+                        }
+                        WorkerMessage::SpeakerModelLoaded => {
+                            state.speaker_model_loaded.set(true);
+                        }
+                        WorkerMessage::TranscriptionPartial { text, channel } => {
+                            state.partial_text.update(|p| {
+                                p.insert(channel, text);
+                            });
+                        }
+                        WorkerMessage::VoiceActivity { speaking, channel, .. } => {
+                            state.speech_active.update(|s| {
+                                s.insert(channel, speaking);
+                            });
+                        }
+                        WorkerMessage::QueueDepth { depth } => {
+                            state.whisper_queue_depth.set(depth);
                         }
                         WorkerMessage::Error { message } => {
+                            // A failed load leaves no model to transcribe with
+                            if state.whisper_status.get_untracked() == ModelStatus::Loading {
+                                state.whisper_status.set(ModelStatus::Error);
+                            }
                             state.error_message.set(Some(message));
                         }
                         _ => {}
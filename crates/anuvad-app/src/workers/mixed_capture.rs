@@ -5,16 +5,19 @@ use web_sys::{
 };
 use std::cell::RefCell;
 
-use crate::workers::bridge::{self, WorkerMessage};
+use crate::workers::bridge::{self, Channel, WorkerMessage};
 
 thread_local! {
-    static MIXED_AUDIO_CTX: RefCell<Option<AudioContext>> = RefCell::new(None);
-    static MIXED_SCRIPT_PROCESSOR: RefCell<Option<ScriptProcessorNode>> = RefCell::new(None);
-    static MIXED_MIC_STREAM: RefCell<Option<MediaStream>> = RefCell::new(None);
-    static MIXED_TAB_STREAM: RefCell<Option<MediaStream>> = RefCell::new(None);
+    static MIXED_AUDIO_CTX: RefCell<Option<AudioContext>> = const { RefCell::new(None) };
+    static MIXED_SCRIPT_PROCESSOR: RefCell<Option<ScriptProcessorNode>> = const { RefCell::new(None) };
+    static MIXED_MIC_STREAM: RefCell<Option<MediaStream>> = const { RefCell::new(None) };
+    static MIXED_TAB_STREAM: RefCell<Option<MediaStream>> = const { RefCell::new(None) };
 }
 
-pub async fn start_mixed_capture() -> Result<(), String> {
+/// Captures the microphone and tab/system audio together. With
+/// `separate_channels` each is sent as its own channel ("Me" and "Remote")
+/// instead of being mixed.
+pub async fn start_mixed_capture(separate_channels: bool) -> Result<(), String> {
     // 1. Get mic stream via getUserMedia
     let window = web_sys::window().ok_or("No window")?;
     let navigator = window.navigator();
@@ -37,7 +40,8 @@ pub async fn start_mixed_capture() -> Result<(), String> {
     // 2. Get system audio stream
     let tab_stream = get_system_audio_stream().await?;
 
-    // 3. Create one AudioContext at 16kHz
+    // 3. Create one AudioContext, at 16kHz if the browser allows; the worker
+    //    resamples whatever rate it runs at
     let opts = AudioContextOptions::new();
     opts.set_sample_rate(16000.0);
     let ctx = AudioContext::new_with_context_options(&opts)
@@ -52,26 +56,38 @@ pub async fn start_mixed_capture() -> Result<(), String> {
         .create_media_stream_source(&tab_stream)
         .map_err(|e| format!("createMediaStreamSource (tab) failed: {e:?}"))?;
 
-    // 5. Create a single ScriptProcessorNode — WebAudio mixes both inputs automatically
+    // 5. Create a single ScriptProcessorNode — WebAudio mixes both inputs
+    //    automatically, unless they are kept on channels 0 (mic) and 1 (tab)
+    let input_channels = if separate_channels { 2 } else { 1 };
     let processor = ctx
         .create_script_processor_with_buffer_size_and_number_of_input_channels_and_number_of_output_channels(
-            4096, 1, 1,
+            4096, input_channels, 1,
         )
         .map_err(|e| format!("createScriptProcessor failed: {e:?}"))?;
 
-    // 6. onaudioprocess — sends mixed PCM to whisper + updates audio level
+    // 6. onaudioprocess — sends PCM to whisper per channel + updates audio level
     let onaudioprocess = Closure::wrap(Box::new(move |event: web_sys::AudioProcessingEvent| {
         let input_buffer = event.input_buffer().unwrap();
-        let channel_data = input_buffer.get_channel_data(0).unwrap();
-
-        let rms: f32 = (channel_data.iter().map(|s| s * s).sum::<f32>()
-            / channel_data.len() as f32)
-            .sqrt();
-
-        let msg = WorkerMessage::Transcribe {
-            audio: channel_data.to_vec(),
+        let channels: &[(u32, Option<Channel>)] = if separate_channels {
+            &[(0, Some(Channel::Me)), (1, Some(Channel::Remote))]
+        } else {
+            &[(0, None)]
         };
-        bridge::send_to_whisper(&msg);
+
+        let (mut energy, mut samples) = (0.0f32, 0usize);
+        for &(index, channel) in channels {
+            let channel_data = input_buffer.get_channel_data(index).unwrap();
+            energy += channel_data.iter().map(|s| s * s).sum::<f32>();
+            samples += channel_data.len();
+
+            let msg = WorkerMessage::Transcribe {
+                audio: channel_data,
+                sample_rate: input_buffer.sample_rate() as u32,
+                channel,
+            };
+            bridge::send_to_whisper(&msg);
+        }
+        let rms = (energy / samples.max(1) as f32).sqrt();
 
         update_audio_level(rms as f64);
     }) as Box<dyn FnMut(web_sys::AudioProcessingEvent)>);
@@ -79,13 +95,29 @@ pub async fn start_mixed_capture() -> Result<(), String> {
     processor.set_onaudioprocess(Some(onaudioprocess.as_ref().unchecked_ref()));
     onaudioprocess.forget();
 
-    // Connect both sources to the single processor, then processor to destination
-    mic_source
-        .connect_with_audio_node(&processor)
-        .map_err(|e| format!("connect mic failed: {e:?}"))?;
-    tab_source
-        .connect_with_audio_node(&processor)
-        .map_err(|e| format!("connect tab failed: {e:?}"))?;
+    // Connect both sources to the single processor, through a merger that
+    // keeps them on separate channels if asked, then processor to destination
+    if separate_channels {
+        let merger = ctx
+            .create_channel_merger_with_number_of_inputs(2)
+            .map_err(|e| format!("createChannelMerger failed: {e:?}"))?;
+        mic_source
+            .connect_with_audio_node_and_output_and_input(&merger, 0, 0)
+            .map_err(|e| format!("connect mic failed: {e:?}"))?;
+        tab_source
+            .connect_with_audio_node_and_output_and_input(&merger, 0, 1)
+            .map_err(|e| format!("connect tab failed: {e:?}"))?;
+        merger
+            .connect_with_audio_node(&processor)
+            .map_err(|e| format!("connect merger failed: {e:?}"))?;
+    } else {
+        mic_source
+            .connect_with_audio_node(&processor)
+            .map_err(|e| format!("connect mic failed: {e:?}"))?;
+        tab_source
+            .connect_with_audio_node(&processor)
+            .map_err(|e| format!("connect tab failed: {e:?}"))?;
+    }
     processor
         .connect_with_audio_node(&ctx.destination())
         .map_err(|e| format!("connect to destination failed: {e:?}"))?;
@@ -2,16 +2,10 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{Cache, Request, Response};
 
-const CACHE_NAME: &str = "anuvad-models-v1";
+use crate::state::WhisperModel;
+use crate::workers::bridge::{self, WeightPrecision};
 
-const WHISPER_MODEL_URL: &str =
-    "https://huggingface.co/openai/whisper-small/resolve/main/model.safetensors";
-const WHISPER_TOKENIZER_URL: &str =
-    "https://huggingface.co/openai/whisper-small/resolve/main/tokenizer.json";
-const WHISPER_CONFIG_URL: &str =
-    "https://huggingface.co/openai/whisper-small/resolve/main/config.json";
-const WHISPER_MEL_URL: &str =
-    "https://huggingface.co/openai/whisper-small/resolve/main/melfilters.bytes";
+const CACHE_NAME: &str = "anuvad-models-v1";
 
 const PHI_MODEL_URL: &str =
     "https://huggingface.co/microsoft/Phi-3.5-mini-instruct-gguf/resolve/main/Phi-3.5-mini-instruct-Q4_K_M.gguf";
@@ -30,6 +24,7 @@ async fn open_cache() -> Result<Cache, String> {
         .map_err(|_| "Not a Cache".to_string())
 }
 
+#[allow(dead_code)]
 async fn is_cached(url: &str) -> Result<bool, String> {
     let cache = open_cache().await?;
     let request = Request::new_with_str(url).map_err(|e| format!("{e:?}"))?;
@@ -146,16 +141,17 @@ async fn fetch_with_progress(
 }
 
 pub async fn download_whisper_model(
+    model: WhisperModel,
     on_progress: impl Fn(f64) + Clone + 'static,
 ) -> Result<(), String> {
     // Request persistent storage
     request_persistent_storage().await;
 
     let urls = [
-        WHISPER_MODEL_URL,
-        WHISPER_TOKENIZER_URL,
-        WHISPER_CONFIG_URL,
-        WHISPER_MEL_URL,
+        whisper_model_url(model),
+        whisper_tokenizer_url(model),
+        whisper_config_url(model),
+        whisper_generation_config_url(model),
     ];
 
     let total = urls.len() as f64;
@@ -194,6 +190,48 @@ pub async fn get_cached_bytes(url: &str) -> Result<Vec<u8>, String> {
     fetch_with_progress(url, |_| {}).await
 }
 
+async fn get_cached_text(url: &str) -> Result<String, String> {
+    String::from_utf8(get_cached_bytes(url).await?).map_err(|e| format!("{url}: {e}"))
+}
+
+/// A downloaded file as a JS array, so large weights are never copied into
+/// this module's memory.
+async fn get_cached_array(url: &str) -> Result<js_sys::Uint8Array, String> {
+    let cache = open_cache().await?;
+    let request = Request::new_with_str(url).map_err(|e| format!("{e:?}"))?;
+    let match_result = JsFuture::from(cache.match_with_request(&request))
+        .await
+        .map_err(|e| format!("{e:?}"))?;
+    if match_result.is_undefined() {
+        return Err(format!("{url} is not downloaded"));
+    }
+    let response: Response = match_result.dyn_into().map_err(|_| "Not a Response")?;
+    let ab = JsFuture::from(response.array_buffer().map_err(|e| format!("{e:?}"))?)
+        .await
+        .map_err(|e| format!("{e:?}"))?;
+    Ok(js_sys::Uint8Array::new(&ab))
+}
+
+/// Hands a downloaded checkpoint to the whisper worker, which answers with
+/// `ModelLoaded`.
+pub async fn load_whisper_model(
+    model: WhisperModel,
+    precision: WeightPrecision,
+) -> Result<(), String> {
+    let tokenizer_json = get_cached_text(&whisper_tokenizer_url(model)).await?;
+    let config_json = get_cached_text(&whisper_config_url(model)).await?;
+    // Only needed for the model's alignment heads
+    let generation_config_json = get_cached_text(&whisper_generation_config_url(model)).await.ok();
+    let model_bytes = get_cached_array(&whisper_model_url(model)).await?;
+    bridge::load_whisper_model(
+        model_bytes,
+        tokenizer_json,
+        config_json,
+        generation_config_json,
+        precision,
+    )
+}
+
 async fn request_persistent_storage() {
     let window = web_sys::window().unwrap();
     let navigator = window.navigator();
@@ -205,21 +243,25 @@ async fn request_persistent_storage() {
     }
 }
 
+fn hub_url(repo: &str, file: &str) -> String {
+    format!("https://huggingface.co/{repo}/resolve/main/{file}")
+}
+
 // Public URLs for use by worker crates
-pub const fn whisper_model_url() -> &'static str {
-    WHISPER_MODEL_URL
+pub fn whisper_model_url(model: WhisperModel) -> String {
+    hub_url(model.repo(), "model.safetensors")
 }
 
-pub const fn whisper_tokenizer_url() -> &'static str {
-    WHISPER_TOKENIZER_URL
+pub fn whisper_tokenizer_url(model: WhisperModel) -> String {
+    hub_url(model.repo(), "tokenizer.json")
 }
 
-pub const fn whisper_config_url() -> &'static str {
-    WHISPER_CONFIG_URL
+pub fn whisper_config_url(model: WhisperModel) -> String {
+    hub_url(model.repo(), "config.json")
 }
 
-pub const fn whisper_mel_url() -> &'static str {
-    WHISPER_MEL_URL
+pub fn whisper_generation_config_url(model: WhisperModel) -> String {
+    hub_url(model.repo(), "generation_config.json")
 }
 
 pub const fn phi_model_url() -> &'static str {
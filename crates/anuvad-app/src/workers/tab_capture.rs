@@ -6,9 +6,9 @@ use std::cell::RefCell;
 use crate::workers::bridge::{self, WorkerMessage};
 
 thread_local! {
-    static TAB_AUDIO_CTX: RefCell<Option<AudioContext>> = RefCell::new(None);
-    static TAB_SCRIPT_PROCESSOR: RefCell<Option<ScriptProcessorNode>> = RefCell::new(None);
-    static TAB_MEDIA_STREAM: RefCell<Option<MediaStream>> = RefCell::new(None);
+    static TAB_AUDIO_CTX: RefCell<Option<AudioContext>> = const { RefCell::new(None) };
+    static TAB_SCRIPT_PROCESSOR: RefCell<Option<ScriptProcessorNode>> = const { RefCell::new(None) };
+    static TAB_MEDIA_STREAM: RefCell<Option<MediaStream>> = const { RefCell::new(None) };
 }
 
 pub async fn start_tab_capture() -> Result<(), String> {
@@ -59,7 +59,8 @@ pub async fn start_tab_capture() -> Result<(), String> {
         .dyn_into()
         .map_err(|_| "tabCapture did not return a MediaStream")?;
 
-    // Create AudioContext at 16kHz (same pipeline as mic capture)
+    // Ask for 16kHz (same pipeline as mic capture); tab audio often stays at
+    // 44.1 or 48kHz, which the worker resamples
     let opts = AudioContextOptions::new();
     opts.set_sample_rate(16000.0);
     let ctx = AudioContext::new_with_context_options(&opts)
@@ -85,6 +86,8 @@ pub async fn start_tab_capture() -> Result<(), String> {
 
         let msg = WorkerMessage::Transcribe {
             audio: channel_data.to_vec(),
+            sample_rate: input_buffer.sample_rate() as u32,
+            channel: None,
         };
         bridge::send_to_whisper(&msg);
 
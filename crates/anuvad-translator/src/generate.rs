@@ -1,4 +1,3 @@
-use candle_core::{Device, Tensor};
 use tokenizers::Tokenizer;
 
 use crate::model::QuantizedModel;
@@ -9,6 +9,12 @@ pub struct TranslatorWorker {
     generator: Option<generate::TextGenerator>,
 }
 
+impl Default for TranslatorWorker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl TranslatorWorker {
     #[wasm_bindgen(constructor)]
@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+
+use crate::channels::Channel;
+use crate::decoding;
+use crate::safeguards::Safeguard;
+use crate::timestamps::Segment;
+
+// Hypothesis words starting this long before the last commit may still be new
+const COMMIT_TIME_TOLERANCE: f64 = 0.1;
+// Longest run of words matched against the committed tail to drop repeats
+const MAX_OVERLAP_WORDS: usize = 5;
+// Committed words kept for overlap checks and prompting
+const MAX_COMMITTED_WORDS: usize = 200;
+// A silence this long between words may be a change of speaker
+const SEGMENT_PAUSE_SECONDS: f64 = 0.5;
+
+/// A word (or, in scripts without spaces, a character run) with its tokens
+/// and approximate stream times.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimedWord {
+    /// Decoded text, with its leading space if it has one.
+    pub text: String,
+    pub tokens: Vec<u32>,
+    /// Log-probability of each of `tokens`.
+    pub logprobs: Vec<f32>,
+    pub start: f64,
+    pub end: f64,
+    /// Confidence in the word: the mean probability of its tokens.
+    pub probability: f32,
+}
+
+impl TimedWord {
+    pub fn new(text: String, tokens: Vec<u32>, logprobs: Vec<f32>, start: f64, end: f64) -> Self {
+        let probabilities: Vec<f32> = logprobs.iter().map(|l| l.exp()).collect();
+        Self {
+            text,
+            tokens,
+            logprobs,
+            start,
+            end,
+            probability: decoding::mean(&probabilities) as f32,
+        }
+    }
+
+    fn matches(&self, other: &TimedWord) -> bool {
+        self.text.trim() == other.text.trim()
+    }
+}
+
+/// LocalAgreement-2 streaming policy: a word is committed once two
+/// consecutive hypotheses for the growing buffer agree on it, so committed
+/// text never changes and only the tail after it is tentative.
+#[derive(Debug, Default)]
+pub struct LocalAgreement {
+    committed: Vec<TimedWord>,
+    last_committed_end: f64,
+    tentative: Vec<TimedWord>,
+}
+
+impl LocalAgreement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compares the words of a new hypothesis with the previous one and
+    /// returns those newly committed.
+    pub fn insert(&mut self, hypothesis: Vec<TimedWord>) -> Vec<TimedWord> {
+        let mut new: Vec<TimedWord> = hypothesis
+            .into_iter()
+            .filter(|w| w.start > self.last_committed_end - COMMIT_TIME_TOLERANCE)
+            .collect();
+
+        // Words re-heard at the start of the buffer repeat the committed tail
+        let max_overlap = MAX_OVERLAP_WORDS.min(new.len()).min(self.committed.len());
+        for n in (1..=max_overlap).rev() {
+            let tail = &self.committed[self.committed.len() - n..];
+            if tail.iter().zip(&new[..n]).all(|(a, b)| a.matches(b)) {
+                new.drain(..n);
+                break;
+            }
+        }
+
+        let agreed = new
+            .iter()
+            .zip(&self.tentative)
+            .take_while(|(a, b)| a.matches(b))
+            .count();
+        let committed: Vec<TimedWord> = new.drain(..agreed).collect();
+        self.commit(&committed);
+        self.tentative = new;
+
+        committed
+    }
+
+    /// Commits the tentative words, once no later audio can change them.
+    pub fn flush(&mut self) -> Vec<TimedWord> {
+        let words = std::mem::take(&mut self.tentative);
+        self.commit(&words);
+        words
+    }
+
+    fn commit(&mut self, words: &[TimedWord]) {
+        if let Some(last) = words.last() {
+            self.last_committed_end = last.end;
+        }
+        self.committed.extend_from_slice(words);
+        let excess = self.committed.len().saturating_sub(MAX_COMMITTED_WORDS);
+        self.committed.drain(..excess);
+    }
+
+    /// Words after the committed text, which the next hypothesis may revise.
+    pub fn tentative(&self) -> &[TimedWord] {
+        &self.tentative
+    }
+
+    /// Stream time up to which the transcript is committed.
+    pub fn last_committed_end(&self) -> f64 {
+        self.last_committed_end
+    }
+
+    /// Tokens of committed words that ended before `seconds`, to prompt the
+    /// decoder for audio that starts there.
+    pub fn prompt(&self, seconds: f64) -> Vec<u32> {
+        self.committed
+            .iter()
+            .filter(|w| w.end <= seconds)
+            .flat_map(|w| w.tokens.iter().copied())
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// What one streaming update adds to the transcript.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamingUpdate {
+    /// Newly committed text; never revised by later updates.
+    pub text: String,
+    pub language: Option<String>,
+    pub language_probability: Option<f32>,
+    /// The committed text as a timed segment, when timestamps are enabled.
+    pub segments: Vec<Segment>,
+    /// Newly committed words with their confidence.
+    pub words: Vec<TimedWord>,
+    /// Uncommitted tail, replacing the previous update's.
+    pub partial: String,
+    /// Safeguards that removed text from this update's hypothesis.
+    pub safeguards: Vec<Safeguard>,
+    /// Share of input samples that were clipped since the last update.
+    pub clipping: f32,
+    /// Input the update was decoded from, when inputs are kept apart.
+    pub channel: Option<Channel>,
+}
+
+/// Joins words into one segment spanning them, or `None` when empty.
+fn words_to_segment(words: &[TimedWord]) -> Option<Segment> {
+    let (first, last) = (words.first()?, words.last()?);
+    Some(Segment::new(
+        first.start,
+        last.end,
+        words_to_text(words),
+        words.iter().flat_map(|w| w.tokens.iter().copied()).collect(),
+        words.iter().flat_map(|w| w.logprobs.iter().copied()).collect(),
+    ))
+}
+
+/// Splits words into segments at pauses and at `boundaries` (times where
+/// decoded segments end), so that each segment can get its own speaker.
+pub fn words_to_segments(words: &[TimedWord], boundaries: &[f64]) -> Vec<Segment> {
+    let continues = |a: &TimedWord, b: &TimedWord| {
+        b.start - a.end <= SEGMENT_PAUSE_SECONDS
+            && !boundaries.iter().any(|&t| a.end <= t + 1e-3 && b.start >= t - 1e-3)
+    };
+    words.chunk_by(continues).filter_map(words_to_segment).collect()
+}
+
+pub fn words_to_text(words: &[TimedWord]) -> String {
+    words.iter().map(|w| w.text.as_str()).collect::<String>().trim().to_string()
+}
+
+/// Whether `c` belongs to a script written without spaces between words,
+/// where each character counts as a word for agreement.
+pub fn is_unspaced_script(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0E00}'..='\u{0EFF}' // Thai, Lao
+            | '\u{1000}'..='\u{109F}' // Myanmar
+            | '\u{1780}'..='\u{17FF}' // Khmer
+            | '\u{2E80}'..='\u{9FFF}' // CJK, kana
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FF00}'..='\u{FFEF}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Words of `text` a quarter second apart, starting at `start`
+    fn words(text: &str, start: f64) -> Vec<TimedWord> {
+        text.split_whitespace()
+            .enumerate()
+            .map(|(i, w)| {
+                let t = start + 0.25 * i as f64;
+                let token = w.bytes().map(u32::from).sum();
+                TimedWord::new(format!(" {w}"), vec![token], vec![-0.1], t, t + 0.2)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn commits_words_two_hypotheses_agree_on() {
+        let mut agreement = LocalAgreement::new();
+        assert!(agreement.insert(words("the cat", 0.0)).is_empty());
+        assert_eq!(words_to_text(agreement.tentative()), "the cat");
+
+        let committed = agreement.insert(words("the cat sat down", 0.0));
+        assert_eq!(words_to_text(&committed), "the cat");
+        assert_eq!(words_to_text(agreement.tentative()), "sat down");
+        assert_eq!(agreement.last_committed_end(), 0.45);
+
+        // A revised tail commits nothing and replaces the tentative words
+        assert!(agreement.insert(words("the cat sang", 0.0)).is_empty());
+        assert_eq!(words_to_text(agreement.tentative()), "sang");
+    }
+
+    #[test]
+    fn drops_words_repeating_the_committed_tail() {
+        let mut agreement = LocalAgreement::new();
+        agreement.insert(words("one two three", 0.0));
+        agreement.insert(words("one two three", 0.0));
+        agreement.flush();
+        // Timings drift, so the last words are re-heard after the commit
+        agreement.insert(words("two three four five", 0.65));
+        let committed = agreement.insert(words("two three four five", 0.65));
+        assert_eq!(words_to_text(&committed), "four five");
+    }
+
+    #[test]
+    fn flush_commits_the_tentative_words() {
+        let mut agreement = LocalAgreement::new();
+        agreement.insert(words("hello there", 0.0));
+        assert_eq!(words_to_text(&agreement.flush()), "hello there");
+        assert!(agreement.tentative().is_empty());
+        assert!(agreement.flush().is_empty());
+    }
+
+    #[test]
+    fn prompts_with_words_before_the_window() {
+        let mut agreement = LocalAgreement::new();
+        let heard = words("a b c", 0.0);
+        agreement.insert(heard.clone());
+        agreement.insert(heard.clone());
+        assert_eq!(agreement.prompt(0.45), [heard[0].tokens[0], heard[1].tokens[0]]);
+        agreement.clear();
+        assert!(agreement.prompt(10.0).is_empty());
+    }
+
+    #[test]
+    fn unspaced_scripts() {
+        assert!(is_unspaced_script('中'));
+        assert!(is_unspaced_script('ก'));
+        assert!(!is_unspaced_script('a'));
+        assert!(!is_unspaced_script('न'));
+    }
+
+    #[test]
+    fn splits_segments_at_pauses_and_boundaries() {
+        let mut heard = words("a b c", 0.0);
+        heard.extend(words("d e", 2.0));
+        let spans = |segments: Vec<Segment>| -> Vec<(f64, f64, String)> {
+            segments.into_iter().map(|s| (s.start, s.end, s.text)).collect()
+        };
+
+        assert_eq!(
+            spans(words_to_segments(&heard, &[])),
+            [(0.0, 0.7, "a b c".into()), (2.0, 2.45, "d e".into())]
+        );
+        // A decoded segment ending after "a" splits there too
+        assert_eq!(
+            spans(words_to_segments(&heard, &[0.22])),
+            [
+                (0.0, 0.2, "a".into()),
+                (0.25, 0.7, "b c".into()),
+                (2.0, 2.45, "d e".into())
+            ]
+        );
+        assert!(words_to_segments(&[], &[1.0]).is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+use serde::Deserialize;
+use std::ops::Range;
+
+// Encoder output frames per second of audio (1500 per 30-second window)
+const FRAMES_PER_SECOND: f64 = 50.0;
+// Width of the median filter that smooths each head's attention over time
+const MEDIAN_FILTER_WIDTH: usize = 7;
+
+/// The heads used when the model ships no alignment heads: every head in
+/// the second half of the decoder layers, as in the reference
+/// implementation.
+pub fn default_heads(n_layers: usize, n_heads: usize) -> Vec<(usize, usize)> {
+    (n_layers / 2..n_layers)
+        .flat_map(|layer| (0..n_heads).map(move |head| (layer, head)))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct GenerationConfig {
+    #[serde(default)]
+    alignment_heads: Option<Vec<(usize, usize)>>,
+}
+
+/// The `alignment_heads` (layer, head) pairs of a Hugging Face
+/// `generation_config.json`, if it lists any.
+pub fn heads_from_generation_config(json: &str) -> Result<Option<Vec<(usize, usize)>>, String> {
+    let config: GenerationConfig = serde_json::from_str(json)
+        .map_err(|e| format!("Generation config parse error: {e}"))?;
+    Ok(config.alignment_heads.filter(|heads| !heads.is_empty()))
+}
+
+/// Aligns tokens to audio from the pre-softmax cross-attention scores of the
+/// alignment heads, one `n_tokens` x `n_frames` matrix per head. Each
+/// position attends to the audio of the token it predicts, so the result
+/// holds, for each position in `rows`, the time in seconds at which the
+/// following token starts.
+///
+/// Follows the reference `find_alignment`: each head's scores are softmaxed
+/// over time, standardised per frame across tokens, median filtered and
+/// averaged over heads, then a dynamic time warping path through the
+/// resulting cost matrix assigns frames to tokens.
+pub fn token_times(
+    heads: &[Vec<f32>],
+    n_tokens: usize,
+    n_frames: usize,
+    rows: Range<usize>,
+) -> Vec<f64> {
+    if heads.is_empty() || n_frames == 0 || rows.is_empty() {
+        return vec![0.0; rows.len()];
+    }
+
+    let mut matrix = vec![0.0f32; rows.len() * n_frames];
+    for qk in heads {
+        let mut weights = qk.clone();
+        for row in weights.chunks_exact_mut(n_frames) {
+            softmax(row);
+        }
+        standardize_columns(&mut weights, n_tokens, n_frames);
+        for (row, out) in weights[rows.start * n_frames..rows.end * n_frames]
+            .chunks_exact_mut(n_frames)
+            .zip(matrix.chunks_exact_mut(n_frames))
+        {
+            median_filter(row, MEDIAN_FILTER_WIDTH);
+            for (o, w) in out.iter_mut().zip(row.iter()) {
+                *o += w / heads.len() as f32;
+            }
+        }
+    }
+
+    // The path favours high attention, so its cost is the negated weight
+    for w in &mut matrix {
+        *w = -*w;
+    }
+    let path = dtw(&matrix, rows.len(), n_frames);
+
+    // Each token starts at the first frame the path assigns to its row
+    let mut times = Vec::with_capacity(rows.len());
+    let mut last_row = None;
+    for (row, frame) in path {
+        if last_row != Some(row) {
+            times.push(frame as f64 / FRAMES_PER_SECOND);
+            last_row = Some(row);
+        }
+    }
+    times
+}
+
+/// Lowest-cost monotonic path from the first cell of `cost` to the last,
+/// moving down, right or diagonally. Returns the (row, column) cells in
+/// order.
+fn dtw(cost: &[f32], n_rows: usize, n_cols: usize) -> Vec<(usize, usize)> {
+    let width = n_cols + 1;
+    let mut total = vec![f32::INFINITY; (n_rows + 1) * width];
+    // 0: diagonal, 1: from the row above, 2: from the column to the left
+    let mut trace = vec![0u8; (n_rows + 1) * width];
+    total[0] = 0.0;
+
+    for j in 1..=n_cols {
+        for i in 1..=n_rows {
+            let diagonal = total[(i - 1) * width + j - 1];
+            let up = total[(i - 1) * width + j];
+            let left = total[i * width + j - 1];
+            let (best, step) = if diagonal < up && diagonal < left {
+                (diagonal, 0)
+            } else if up < diagonal && up < left {
+                (up, 1)
+            } else {
+                (left, 2)
+            };
+            total[i * width + j] = cost[(i - 1) * n_cols + j - 1] + best;
+            trace[i * width + j] = step;
+        }
+    }
+
+    let (mut i, mut j) = (n_rows, n_cols);
+    let mut path = Vec::with_capacity(n_rows + n_cols);
+    while i > 0 || j > 0 {
+        path.push((i.saturating_sub(1), j.saturating_sub(1)));
+        let step = if i == 0 {
+            2
+        } else if j == 0 {
+            1
+        } else {
+            trace[i * width + j]
+        };
+        match step {
+            0 => {
+                i -= 1;
+                j -= 1;
+            }
+            1 => i -= 1,
+            _ => j -= 1,
+        }
+    }
+    path.reverse();
+    path
+}
+
+fn softmax(values: &mut [f32]) {
+    let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for v in values.iter_mut() {
+        *v = (*v - max).exp();
+        sum += *v;
+    }
+    for v in values.iter_mut() {
+        *v /= sum;
+    }
+}
+
+// Zero mean and unit population standard deviation down each column, as
+// the reference's `torch.std_mean(..., unbiased=False)`
+fn standardize_columns(values: &mut [f32], n_rows: usize, n_cols: usize) {
+    if n_rows == 0 {
+        return;
+    }
+    for col in 0..n_cols {
+        let column = || (0..n_rows).map(|row| values[row * n_cols + col]);
+        let mean = column().sum::<f32>() / n_rows as f32;
+        let var = column().map(|v| (v - mean).powi(2)).sum::<f32>() / n_rows as f32;
+        let std = var.sqrt().max(f32::EPSILON);
+        for row in 0..n_rows {
+            let v = &mut values[row * n_cols + col];
+            *v = (*v - mean) / std;
+        }
+    }
+}
+
+// Median over a window centred on each value, reflecting at the edges
+fn median_filter(values: &mut [f32], width: usize) {
+    let pad = width / 2;
+    if values.len() <= pad {
+        return;
+    }
+    let n = values.len() as isize;
+    let reflect = |i: isize| {
+        let i = if i < 0 { -i } else { i };
+        let i = if i >= n { 2 * (n - 1) - i } else { i };
+        i as usize
+    };
+    let source = values.to_vec();
+    let mut window = vec![0.0; width];
+    for (i, v) in values.iter_mut().enumerate() {
+        for (k, w) in window.iter_mut().enumerate() {
+            *w = source[reflect(i as isize + k as isize - pad as isize)];
+        }
+        window.sort_by(f32::total_cmp);
+        *v = window[pad];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dtw_follows_the_cheap_cells() {
+        // Cheap cells down the diagonal and along the last row
+        #[rustfmt::skip]
+        let cost = [
+            0.1, 0.2, 5.0, 5.0,
+            5.0, 0.3, 5.0, 5.0,
+            5.0, 5.0, 0.4, 0.5,
+        ];
+        let path = dtw(&cost, 3, 4);
+        assert_eq!(path, [(0, 0), (1, 1), (2, 2), (2, 3)]);
+        assert_eq!(dtw(&[5.0], 1, 1), [(0, 0)]);
+    }
+
+    #[test]
+    fn token_times_follow_attention() {
+        // Token i attends to frames 10i..10i + 10
+        let (n_tokens, n_frames) = (4, 40);
+        let qk: Vec<f32> = (0..n_tokens * n_frames)
+            .map(|i| if i % n_frames / 10 == i / n_frames { 5.0 } else { 0.0 })
+            .collect();
+        let times = token_times(&[qk.clone(), qk], n_tokens, n_frames, 0..n_tokens);
+        assert_eq!(times, [0.0, 0.2, 0.4, 0.6]);
+        assert_eq!(token_times(&[], n_tokens, n_frames, 1..3), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn standardizes_with_the_population_deviation() {
+        // Columns [1, 3] and [2, 2], row-major
+        let mut values = [1.0, 2.0, 3.0, 2.0];
+        standardize_columns(&mut values, 2, 2);
+        assert_eq!(values, [-1.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn median_filter_removes_spikes() {
+        let mut values = [1.0, 1.0, 1.0, 9.0, 1.0, 1.0, 1.0, 1.0];
+        median_filter(&mut values, 7);
+        assert_eq!(values, [1.0; 8]);
+    }
+
+    #[test]
+    fn reads_alignment_heads() {
+        let json = r#"{"alignment_heads": [[2, 0], [3, 5]], "max_length": 448}"#;
+        assert_eq!(heads_from_generation_config(json).unwrap(), Some(vec![(2, 0), (3, 5)]));
+        assert_eq!(heads_from_generation_config(r#"{"alignment_heads": []}"#).unwrap(), None);
+        assert_eq!(heads_from_generation_config("{}").unwrap(), None);
+        assert!(heads_from_generation_config("[").is_err());
+        assert_eq!(default_heads(4, 2), [(2, 0), (2, 1), (3, 0), (3, 1)]);
+    }
+}
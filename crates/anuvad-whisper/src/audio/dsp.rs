@@ -0,0 +1,508 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Complex, ComplexFft, SAMPLE_RATE};
+
+// Samples at or beyond this magnitude count as clipped
+const CLIP_LEVEL: f32 = 0.999;
+// Butterworth response for the high-pass
+const HIGH_PASS_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+// Level is measured over 10 ms blocks
+const BLOCK_SAMPLES: usize = 160;
+// Blocks quieter than this don't move the level estimate, so pauses aren't
+// boosted into hiss
+const GATE_DB: f32 = -50.0;
+// Time constants of the level estimate and of the gain following it; slow
+// enough not to pump within a sentence
+const LEVEL_SECONDS: f32 = 0.4;
+const GAIN_SECONDS: f32 = 1.0;
+// Peak mode follows peaks at once and lets them decay over this long
+const PEAK_RELEASE_SECONDS: f32 = 2.0;
+// -1 dBFS
+const LIMITER_CEILING: f32 = 0.891;
+const LIMITER_RELEASE_SECONDS: f32 = 0.05;
+// Noise reducer frames: 32 ms at 50% overlap
+const NR_FRAME: usize = 512;
+const NR_HOP: usize = NR_FRAME / 2;
+// Lowest gain a bin is turned down to; removing noise entirely leaves
+// warbling "musical noise"
+const NR_FLOOR: f32 = 0.1;
+// Bins within this factor of the noise estimate are averaged into it;
+// louder ones are taken as speech and only let it creep up, so a louder fan
+// is eventually learned but speech isn't
+const NOISE_GATE: f32 = 4.0;
+const NOISE_ADAPT: f32 = 0.1;
+const NOISE_RISE: f32 = 1.002;
+
+/// Level normalisation target, in dBFS.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Normalization {
+    /// Average speech level, e.g. -20.
+    Rms { target_db: f32 },
+    /// Recent peaks, e.g. -3.
+    Peak { target_db: f32 },
+}
+
+/// Pre-processing applied to captured audio before the mel spectrogram.
+/// Stages run in field order; each can be switched off.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DspOptions {
+    /// Removes DC offset and rumble below `high_pass_hz`.
+    pub high_pass: bool,
+    pub high_pass_hz: f32,
+    /// Spectral subtraction of steady background noise such as fans or hum.
+    pub noise_reduction: bool,
+    /// Multiple of the noise estimate removed; higher is more aggressive.
+    pub noise_reduction_strength: f32,
+    /// Brings quiet and loud sources to a common level, followed by a
+    /// limiter so boosted peaks don't clip.
+    pub normalize: Option<Normalization>,
+    /// Cap on the normalisation gain, so silence isn't amplified.
+    pub max_gain_db: f32,
+}
+
+impl Default for DspOptions {
+    fn default() -> Self {
+        Self {
+            high_pass: true,
+            high_pass_hz: 80.0,
+            noise_reduction: false,
+            noise_reduction_strength: 1.5,
+            normalize: Some(Normalization::Rms { target_db: -20.0 }),
+            max_gain_db: 30.0,
+        }
+    }
+}
+
+/// Streaming DSP chain for 16 kHz mono audio. Also counts clipped capture
+/// samples, which no later stage can repair.
+pub struct DspChain {
+    options: DspOptions,
+    high_pass: Biquad,
+    noise_reducer: NoiseReducer,
+    normalizer: Normalizer,
+    clipped: usize,
+    seen: usize,
+    // Leading noise reducer output still to drop, so that output sample `i`
+    // is input sample `i` and stream times stay put
+    skip: usize,
+}
+
+impl DspChain {
+    pub fn new(options: DspOptions) -> Self {
+        Self {
+            high_pass: Biquad::high_pass(options.high_pass_hz, HIGH_PASS_Q),
+            noise_reducer: NoiseReducer::new(options.noise_reduction_strength),
+            normalizer: Normalizer::new(options.normalize, options.max_gain_db),
+            skip: if options.noise_reduction { NR_FRAME - NR_HOP } else { 0 },
+            options,
+            clipped: 0,
+            seen: 0,
+        }
+    }
+
+    pub fn options(&self) -> &DspOptions {
+        &self.options
+    }
+
+    /// Counts clipped samples in captured audio at its own rate, before
+    /// resampling smooths or overshoots the flattened peaks.
+    pub fn count_clipping(&mut self, pcm: &[f32]) {
+        self.seen += pcm.len();
+        self.clipped += pcm.iter().filter(|s| s.abs() >= CLIP_LEVEL).count();
+    }
+
+    /// Processes the next chunk. With noise reduction on, output is held
+    /// back until a hop of input is complete, so chunk lengths can differ,
+    /// but the output stays aligned with the input.
+    pub fn process(&mut self, pcm: &[f32]) -> Vec<f32> {
+        let mut out = pcm.to_vec();
+        if self.options.high_pass {
+            self.high_pass.process(&mut out);
+        }
+        if self.options.noise_reduction {
+            out = self.noise_reducer.process(&out);
+            let skipped = self.skip.min(out.len());
+            out.drain(..skipped);
+            self.skip -= skipped;
+        }
+        if self.options.normalize.is_some() {
+            self.normalizer.process(&mut out);
+        }
+        out
+    }
+
+    /// Runs a whole recording through a fresh chain.
+    pub fn process_all(options: DspOptions, pcm: &[f32]) -> Vec<f32> {
+        let held_back = options.noise_reduction;
+        let mut chain = Self::new(options);
+        let mut out = chain.process(pcm);
+        if held_back {
+            out.extend(chain.process(&[0.0; NR_FRAME]));
+        }
+        out.truncate(pcm.len());
+        out
+    }
+
+    /// Share of counted samples clipped since the last call.
+    pub fn take_clipping(&mut self) -> f32 {
+        let share = if self.seen == 0 {
+            0.0
+        } else {
+            self.clipped as f32 / self.seen as f32
+        };
+        self.clipped = 0;
+        self.seen = 0;
+        share
+    }
+}
+
+/// Second-order IIR section, from the Audio EQ Cookbook.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    // Transposed direct form II state
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn high_pass(cutoff_hz: f32, q: f64) -> Self {
+        let w0 = 2.0 * std::f64::consts::PI * cutoff_hz as f64 / SAMPLE_RATE as f64;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        Self {
+            b: [
+                (1.0 + cos) / 2.0 / a0,
+                -(1.0 + cos) / a0,
+                (1.0 + cos) / 2.0 / a0,
+            ],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for s in samples {
+            let x = *s as f64;
+            let y = self.b[0] * x + self.z[0];
+            self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+            self.z[1] = self.b[2] * x - self.a[1] * y;
+            *s = y as f32;
+        }
+    }
+}
+
+/// Slow automatic gain towards a target level, then a peak limiter.
+struct Normalizer {
+    mode: Option<Normalization>,
+    max_gain: f32,
+    // Mean square (RMS mode) or peak envelope (peak mode); 0 until measured
+    level: f32,
+    gain: f32,
+    limiter_gain: f32,
+    block: Vec<f32>,
+}
+
+impl Normalizer {
+    fn new(mode: Option<Normalization>, max_gain_db: f32) -> Self {
+        Self {
+            mode,
+            max_gain: db_to_linear(max_gain_db),
+            level: 0.0,
+            gain: 1.0,
+            limiter_gain: 1.0,
+            block: Vec::with_capacity(BLOCK_SAMPLES),
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let Some(mode) = self.mode else {
+            return;
+        };
+        let gain_rate = rate(1.0 / SAMPLE_RATE as f32, GAIN_SECONDS);
+        let release = rate(1.0 / SAMPLE_RATE as f32, LIMITER_RELEASE_SECONDS);
+
+        for s in samples {
+            self.block.push(*s);
+            if self.block.len() == BLOCK_SAMPLES {
+                self.measure(mode);
+                self.block.clear();
+            }
+
+            let target = match mode {
+                Normalization::Rms { target_db } => db_to_linear(target_db) / self.level.sqrt(),
+                Normalization::Peak { target_db } => db_to_linear(target_db) / self.level,
+            };
+            if target.is_finite() {
+                let target = target.clamp(1.0 / self.max_gain, self.max_gain);
+                self.gain += (target - self.gain) * gain_rate;
+            }
+
+            // Instant attack, so nothing passes the ceiling
+            let y = *s * self.gain;
+            let needed = if y.abs() > LIMITER_CEILING {
+                LIMITER_CEILING / y.abs()
+            } else {
+                1.0
+            };
+            if needed < self.limiter_gain {
+                self.limiter_gain = needed;
+            } else {
+                self.limiter_gain += (1.0 - self.limiter_gain) * release;
+            }
+            *s = (y * self.limiter_gain).clamp(-LIMITER_CEILING, LIMITER_CEILING);
+        }
+    }
+
+    fn measure(&mut self, mode: Normalization) {
+        let block_seconds = BLOCK_SAMPLES as f32 / SAMPLE_RATE as f32;
+        let mean_square =
+            self.block.iter().map(|s| s * s).sum::<f32>() / self.block.len() as f32;
+        if linear_to_db(mean_square.sqrt()) < GATE_DB {
+            return;
+        }
+        let (block_level, level_rate) = match mode {
+            Normalization::Rms { .. } => (mean_square, rate(block_seconds, LEVEL_SECONDS)),
+            Normalization::Peak { .. } => {
+                let peak = self.block.iter().fold(0.0, |m: f32, s| s.abs().max(m));
+                (peak, rate(block_seconds, PEAK_RELEASE_SECONDS))
+            }
+        };
+        let attack = matches!(mode, Normalization::Peak { .. }) && block_level > self.level;
+        self.level = if self.level == 0.0 || attack {
+            block_level
+        } else {
+            self.level + (block_level - self.level) * level_rate
+        };
+    }
+}
+
+/// Spectral subtraction over overlapping square-root Hann frames, with the
+/// noise spectrum averaged over frames without speech.
+struct NoiseReducer {
+    strength: f32,
+    fft: ComplexFft,
+    window: Vec<f32>,
+    // Last NR_FRAME input samples
+    frame: Vec<f32>,
+    // Input since the last frame
+    pending: Vec<f32>,
+    // Overlap-add accumulator
+    output: Vec<f32>,
+    // Per-bin noise power, estimated from the first full frame on
+    noise: Vec<f32>,
+    frames: usize,
+    spectrum: Vec<Complex>,
+    scratch: Vec<Complex>,
+}
+
+impl NoiseReducer {
+    fn new(strength: f32) -> Self {
+        // Square-root periodic Hann: analysis and synthesis together give a
+        // Hann window, which sums to one at 50% overlap
+        let window = (0..NR_FRAME)
+            .map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * i as f64 / NR_FRAME as f64;
+                (0.5 * (1.0 - phase.cos())).sqrt() as f32
+            })
+            .collect();
+        Self {
+            strength,
+            fft: ComplexFft::new(NR_FRAME),
+            window,
+            frame: vec![0.0; NR_FRAME],
+            pending: Vec::with_capacity(NR_HOP),
+            output: vec![0.0; NR_FRAME],
+            noise: Vec::new(),
+            frames: 0,
+            spectrum: vec![Complex::default(); NR_FRAME],
+            scratch: vec![Complex::default(); NR_FRAME],
+        }
+    }
+
+    fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let mut out = Vec::with_capacity(samples.len() + NR_HOP);
+        for &s in samples {
+            self.pending.push(s);
+            if self.pending.len() == NR_HOP {
+                self.frame.copy_within(NR_HOP.., 0);
+                self.frame[NR_FRAME - NR_HOP..].copy_from_slice(&self.pending);
+                self.pending.clear();
+                self.process_frame();
+                out.extend_from_slice(&self.output[..NR_HOP]);
+                self.output.copy_within(NR_HOP.., 0);
+                self.output[NR_FRAME - NR_HOP..].fill(0.0);
+            }
+        }
+        out
+    }
+
+    fn process_frame(&mut self) {
+        for ((z, &x), &w) in self.scratch.iter_mut().zip(&self.frame).zip(&self.window) {
+            *z = Complex { re: x * w, im: 0.0 };
+        }
+        self.fft.process(&self.scratch, &mut self.spectrum);
+
+        // The first frame is half silence, so it passes through unestimated
+        self.frames += 1;
+        let first_full = self.frames == 2;
+        if first_full {
+            self.noise = self.spectrum.iter().map(|z| z.norm_sqr()).collect();
+        } else if self.noise.is_empty() {
+            self.noise = vec![0.0; NR_FRAME];
+        }
+        for (z, noise) in self.spectrum.iter_mut().zip(self.noise.iter_mut()) {
+            let power = z.norm_sqr();
+            if self.frames > 2 {
+                *noise = if power < NOISE_GATE * *noise {
+                    *noise + (power - *noise) * NOISE_ADAPT
+                } else {
+                    *noise * NOISE_RISE
+                };
+            }
+            let gain = if power > 0.0 {
+                (1.0 - self.strength * *noise / power).max(NR_FLOOR * NR_FLOOR).sqrt()
+            } else {
+                NR_FLOOR
+            };
+            // Conjugated for the inverse transform below
+            *z = Complex {
+                re: z.re * gain,
+                im: -z.im * gain,
+            };
+        }
+
+        // ifft(X) = conj(fft(conj(X))) / n; only the real part is kept
+        self.fft.process(&self.spectrum, &mut self.scratch);
+        let scale = 1.0 / NR_FRAME as f32;
+        for ((o, z), &w) in self.output.iter_mut().zip(&self.scratch).zip(&self.window) {
+            *o += z.re * scale * w;
+        }
+    }
+}
+
+// Per-step smoothing coefficient for a time constant
+fn rate(step_seconds: f32, time_constant: f32) -> f32 {
+    1.0 - (-step_seconds / time_constant).exp()
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn linear_to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.max(1e-10).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn only(stage: &str) -> DspOptions {
+        DspOptions {
+            high_pass: stage == "high_pass",
+            noise_reduction: stage == "noise_reduction",
+            normalize: (stage == "normalize").then_some(Normalization::Rms { target_db: -20.0 }),
+            ..DspOptions::default()
+        }
+    }
+
+    fn sine(amplitude: f32, frequency: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * frequency * i as f32;
+                amplitude * (phase / SAMPLE_RATE as f32).sin()
+            })
+            .collect()
+    }
+
+    fn rms(pcm: &[f32]) -> f32 {
+        (pcm.iter().map(|s| s * s).sum::<f32>() / pcm.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn counts_clipping_between_takes() {
+        let mut chain = DspChain::new(DspOptions::default());
+        assert_eq!(chain.take_clipping(), 0.0);
+        chain.count_clipping(&[0.5, 1.0, -1.0, 0.2]);
+        chain.process(&[1.0; 100]);
+        assert_eq!(chain.take_clipping(), 0.5);
+        assert_eq!(chain.take_clipping(), 0.0);
+    }
+
+    #[test]
+    fn high_pass_removes_offset_and_keeps_speech() {
+        let offset = DspChain::process_all(only("high_pass"), &[0.5; SAMPLE_RATE]);
+        assert!(rms(&offset[SAMPLE_RATE / 2..]) < 1e-3);
+
+        let tone = sine(0.5, 1000.0, SAMPLE_RATE);
+        let out = DspChain::process_all(only("high_pass"), &tone);
+        assert!((rms(&out[SAMPLE_RATE / 2..]) / rms(&tone) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn normalizes_towards_the_target() {
+        // -43 dBFS RMS, boosted towards -20
+        let tone = sine(0.01, 300.0, 8 * SAMPLE_RATE);
+        let out = DspChain::process_all(only("normalize"), &tone);
+        let level = linear_to_db(rms(&out[7 * SAMPLE_RATE..]));
+        assert!((level + 20.0).abs() < 1.0, "level {level} dB");
+
+        let loud = sine(1.0, 300.0, SAMPLE_RATE);
+        let out = DspChain::process_all(only("normalize"), &loud);
+        assert!(out.iter().all(|s| s.abs() <= LIMITER_CEILING));
+    }
+
+    #[test]
+    fn noise_reduction_quietens_steady_noise() {
+        let mut state = 7u32;
+        let noise: Vec<f32> = (0..2 * SAMPLE_RATE)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                0.05 * (state as f32 / u32::MAX as f32 - 0.5)
+            })
+            .collect();
+        let out = DspChain::process_all(only("noise_reduction"), &noise);
+        assert_eq!(out.len(), noise.len());
+        assert!(rms(&out[SAMPLE_RATE..]) < 0.5 * rms(&noise[SAMPLE_RATE..]));
+    }
+
+    #[test]
+    fn noise_reduction_keeps_onset_times() {
+        // Quiet hiss, then a tone starting just before a 30 ms VAD frame
+        // ends, so that any delay moves it to the next frame
+        let start = 34 * 480 - 100;
+        let mut state = 3u32;
+        let pcm: Vec<f32> = (0..2 * SAMPLE_RATE)
+            .map(|i| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let hiss = 0.002 * (state as f32 / u32::MAX as f32 - 0.5);
+                let tone = if i >= start { 0.3 * (i as f32 * 0.2).sin() } else { 0.0 };
+                hiss + tone
+            })
+            .collect();
+        let onset = |options: DspOptions| {
+            let mut chain = DspChain::new(options);
+            let mut vad = crate::vad::Vad::new();
+            let events: Vec<_> =
+                pcm.chunks(700).flat_map(|c| vad.process(&chain.process(c))).collect();
+            assert!(events[0].speaking);
+            events[0].time
+        };
+        let plain = onset(only("high_pass"));
+        assert_eq!(plain, 33.0 * 0.03);
+        let reduced = DspOptions {
+            noise_reduction: true,
+            ..only("high_pass")
+        };
+        assert_eq!(onset(reduced), plain);
+    }
+
+    #[test]
+    fn disabled_stages_pass_audio_through() {
+        let tone = sine(0.3, 440.0, 1000);
+        assert_eq!(DspChain::process_all(only("none"), &tone), tone);
+    }
+}
@@ -0,0 +1,161 @@
+use super::RealFft;
+
+// Kaldi's defaults, which speaker embedding models are trained on: 25 ms
+// frames every 10 ms, zero-padded to a power of two for the FFT
+const FRAME_LENGTH: usize = 400;
+const FRAME_SHIFT: usize = 160;
+const PADDED_LENGTH: usize = 512;
+const PREEMPHASIS: f32 = 0.97;
+const LOW_FREQ: f32 = 20.0;
+// Samples are scaled to the 16-bit range Kaldi reads them in
+const INT16_SCALE: f32 = 32768.0;
+
+/// Log mel filterbank energies in the style of Kaldi's `compute-fbank-feats`
+/// (and torchaudio's `kaldi.fbank`): per-frame DC removal, pre-emphasis, a
+/// Hamming window and triangular filters on Kaldi's mel scale.
+pub struct Fbank {
+    n_mels: usize,
+    window: Vec<f32>,
+    // First FFT bin and weights of each filter
+    banks: Vec<(usize, Vec<f32>)>,
+    fft: RealFft,
+    frame: Vec<f32>,
+    power: Vec<f32>,
+}
+
+impl Fbank {
+    pub fn new(n_mels: usize) -> Self {
+        let window = (0..FRAME_LENGTH)
+            .map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * i as f64 / (FRAME_LENGTH - 1) as f64;
+                (0.54 - 0.46 * phase.cos()) as f32
+            })
+            .collect();
+
+        let mel = |hz: f32| 1127.0 * (1.0 + hz / 700.0).ln();
+        let (mel_low, mel_high) = (mel(LOW_FREQ), mel(super::SAMPLE_RATE as f32 / 2.0));
+        let delta = (mel_high - mel_low) / (n_mels + 1) as f32;
+        let bin_width = super::SAMPLE_RATE as f32 / PADDED_LENGTH as f32;
+        let banks = (0..n_mels)
+            .map(|m| {
+                let left = mel_low + m as f32 * delta;
+                let (center, right) = (left + delta, left + 2.0 * delta);
+                let weights: Vec<f32> = (0..PADDED_LENGTH / 2)
+                    .map(|bin| {
+                        let m = mel(bin as f32 * bin_width);
+                        if m > left && m < center {
+                            (m - left) / (center - left)
+                        } else if m >= center && m < right {
+                            (right - m) / (right - center)
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect();
+                let first = weights.iter().position(|&w| w > 0.0).unwrap_or(0);
+                let last = weights.iter().rposition(|&w| w > 0.0).map_or(first, |i| i + 1);
+                (first, weights[first..last].to_vec())
+            })
+            .collect();
+
+        Self {
+            n_mels,
+            window,
+            banks,
+            fft: RealFft::new(PADDED_LENGTH),
+            frame: vec![0.0; PADDED_LENGTH],
+            power: vec![0.0; PADDED_LENGTH / 2 + 1],
+        }
+    }
+
+    /// Features of 16 kHz `pcm` with each bin's mean over time subtracted,
+    /// as `(n_frames, n_mels)` values, row-major. Audio shorter than one
+    /// frame gives no frames.
+    pub fn compute(&mut self, pcm: &[f32]) -> (Vec<f32>, usize) {
+        if pcm.len() < FRAME_LENGTH {
+            return (Vec::new(), 0);
+        }
+        let n_frames = 1 + (pcm.len() - FRAME_LENGTH) / FRAME_SHIFT;
+        let mut features = Vec::with_capacity(n_frames * self.n_mels);
+
+        for f in 0..n_frames {
+            let samples = &pcm[f * FRAME_SHIFT..f * FRAME_SHIFT + FRAME_LENGTH];
+            let mean = samples.iter().sum::<f32>() / FRAME_LENGTH as f32;
+            let frame = &mut self.frame[..FRAME_LENGTH];
+            for (x, &s) in frame.iter_mut().zip(samples) {
+                *x = (s - mean) * INT16_SCALE;
+            }
+            for i in (1..FRAME_LENGTH).rev() {
+                frame[i] -= PREEMPHASIS * frame[i - 1];
+            }
+            frame[0] -= PREEMPHASIS * frame[0];
+            for (x, &w) in frame.iter_mut().zip(&self.window) {
+                *x *= w;
+            }
+            self.frame[FRAME_LENGTH..].fill(0.0);
+
+            self.fft.power_spectrum(&self.frame, &mut self.power);
+            features.extend(self.banks.iter().map(|(first, weights)| {
+                let energy: f32 =
+                    weights.iter().zip(&self.power[*first..]).map(|(w, p)| w * p).sum();
+                energy.max(f32::EPSILON).ln()
+            }));
+        }
+
+        for bin in 0..self.n_mels {
+            let mean = (0..n_frames).map(|f| features[f * self.n_mels + bin]).sum::<f32>()
+                / n_frames as f32;
+            for f in 0..n_frames {
+                features[f * self.n_mels + bin] -= mean;
+            }
+        }
+        (features, n_frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N_MELS: usize = 80;
+
+    #[test]
+    fn frames_are_25ms_every_10ms() {
+        let mut fbank = Fbank::new(N_MELS);
+        assert_eq!(fbank.compute(&[0.1; FRAME_LENGTH - 1]), (Vec::new(), 0));
+        let (features, n_frames) = fbank.compute(&[0.1; 16000]);
+        assert_eq!(n_frames, 98);
+        assert_eq!(features.len(), n_frames * N_MELS);
+    }
+
+    #[test]
+    fn tone_lands_in_its_filter() {
+        // Half a second of a tone at the centre of filter 60, where filters
+        // span several FFT bins, then half a second of silence
+        let mel = |hz: f64| 1127.0 * (1.0 + hz / 700.0).ln();
+        let (mel_low, mel_high) = (mel(LOW_FREQ as f64), mel(8000.0));
+        let center = mel_low + 61.0 * (mel_high - mel_low) / (N_MELS + 1) as f64;
+        let frequency = 700.0 * ((center / 1127.0).exp() - 1.0);
+        let tau = 2.0 * std::f64::consts::PI;
+        let pcm: Vec<f32> = (0..16000)
+            .map(|i| {
+                if i < 8000 {
+                    (0.3 * (tau * frequency * i as f64 / 16000.0).sin()) as f32
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+        let (features, n_frames) = Fbank::new(N_MELS).compute(&pcm);
+        let frame = |f: usize| &features[f * N_MELS..(f + 1) * N_MELS];
+        let (tone, silence) = (frame(10), frame(80));
+        let loudest = (0..N_MELS).max_by(|&a, &b| tone[a].total_cmp(&tone[b])).unwrap();
+        assert_eq!(loudest, 60);
+        assert!(tone[60] > 10.0, "{}", tone[60]);
+        assert!(silence[60] < -10.0, "{}", silence[60]);
+        // Each bin's mean over time is removed
+        let sum: f32 = (0..n_frames).map(|f| frame(f)[60]).sum();
+        assert!(sum.abs() < 1e-2, "{sum}");
+    }
+}
@@ -0,0 +1,659 @@
+use candle_transformers::models::whisper::Config;
+
+pub mod dsp;
+pub mod fbank;
+
+const HOP_LENGTH: usize = 160;
+const N_FFT: usize = 400;
+const CHUNK_LENGTH: usize = 30; // seconds
+const SAMPLE_RATE: usize = 16000;
+const N_SAMPLES: usize = CHUNK_LENGTH * SAMPLE_RATE;
+// Frames in a 30-second window
+pub const N_FRAMES: usize = N_SAMPLES / HOP_LENGTH;
+// Frequency bins of a real FFT of N_FFT samples
+const N_FREQS: usize = N_FFT / 2 + 1;
+
+pub fn pcm_to_mel(config: &Config, pcm: &[f32]) -> Result<Vec<f32>, String> {
+    let filters = mel_filters(config.num_mel_bins);
+    let mut mel = MelSpectrogram::new(config.num_mel_bins, &filters)?;
+    Ok(mel.compute(pcm))
+}
+
+/// The `(n_mels, N_FFT / 2 + 1)` mel filterbank Whisper was trained with,
+/// row-major: librosa's `filters.mel` with Slaney's mel scale and area
+/// normalisation, from 0 Hz to Nyquist.
+pub fn mel_filters(n_mels: usize) -> Vec<f32> {
+    // Slaney's scale is linear below 1 kHz and logarithmic above
+    const LINEAR_STEP: f64 = 200.0 / 3.0;
+    const LOG_START_HZ: f64 = 1000.0;
+    const LOG_START_MEL: f64 = LOG_START_HZ / LINEAR_STEP;
+    let log_step = 6.4f64.ln() / 27.0;
+    let hz_to_mel = |hz: f64| {
+        if hz >= LOG_START_HZ {
+            LOG_START_MEL + (hz / LOG_START_HZ).ln() / log_step
+        } else {
+            hz / LINEAR_STEP
+        }
+    };
+    let mel_to_hz = |mel: f64| {
+        if mel >= LOG_START_MEL {
+            LOG_START_HZ * (log_step * (mel - LOG_START_MEL)).exp()
+        } else {
+            mel * LINEAR_STEP
+        }
+    };
+
+    // Filter edges, evenly spaced in mels
+    let max_mel = hz_to_mel(SAMPLE_RATE as f64 / 2.0);
+    let edges: Vec<f64> = (0..n_mels + 2)
+        .map(|i| mel_to_hz(max_mel * i as f64 / (n_mels + 1) as f64))
+        .collect();
+
+    let mut filters = vec![0.0f32; n_mels * N_FREQS];
+    for (m, row) in filters.as_chunks_mut::<N_FREQS>().0.iter_mut().enumerate() {
+        let (lower, centre, upper) = (edges[m], edges[m + 1], edges[m + 2]);
+        // Each triangle has unit area
+        let norm = 2.0 / (upper - lower);
+        for (bin, weight) in row.iter_mut().enumerate() {
+            let hz = bin as f64 * SAMPLE_RATE as f64 / N_FFT as f64;
+            let rising = (hz - lower) / (centre - lower);
+            let falling = (upper - hz) / (upper - centre);
+            *weight = (rising.min(falling).max(0.0) * norm) as f32;
+        }
+    }
+    filters
+}
+
+/// Log10 mel columns of complete frames seen by `compute_streaming`,
+/// frame-major, starting at stream frame `start`. Each stream keeps its own,
+/// since frames are matched by position alone.
+#[derive(Default)]
+pub struct MelCache {
+    frames: Vec<f32>,
+    start: usize,
+    n_mels: usize,
+}
+
+impl MelCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets every cached frame.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.start = 0;
+    }
+}
+
+/// Log-mel front-end matching the reference Whisper `log_mel_spectrogram`:
+/// a centred, reflect-padded STFT with a periodic Hann window, mel power
+/// in log10, clamped to 8 below the peak and scaled by `(x + 4) / 4`.
+///
+/// Window, twiddles, filter bounds and scratch buffers are built once and
+/// reused across calls.
+pub struct MelSpectrogram {
+    n_mels: usize,
+    filters: Vec<f32>,
+    // Non-zero bin range of each filter row
+    filter_bounds: Vec<(usize, usize)>,
+    window: Vec<f32>,
+    fft: RealFft,
+    frame: Vec<f32>,
+    power: Vec<f32>,
+}
+
+impl MelSpectrogram {
+    /// `filters` is the `(n_mels, N_FFT / 2 + 1)` mel filterbank, row-major.
+    pub fn new(n_mels: usize, filters: &[f32]) -> Result<Self, String> {
+        if filters.len() != n_mels * N_FREQS {
+            return Err(format!(
+                "Mel filter size mismatch: expected {}x{N_FREQS}, got {} values",
+                n_mels,
+                filters.len()
+            ));
+        }
+
+        let filter_bounds = filters
+            .as_chunks::<N_FREQS>()
+            .0
+            .iter()
+            .map(|row| {
+                let lo = row.iter().position(|&w| w != 0.0).unwrap_or(0);
+                let hi = row.iter().rposition(|&w| w != 0.0).map_or(lo, |i| i + 1);
+                (lo, hi)
+            })
+            .collect();
+
+        // Periodic Hann window, as torch.hann_window
+        let window = (0..N_FFT)
+            .map(|i| {
+                0.5 * (1.0 - (2.0 * std::f64::consts::PI * i as f64 / N_FFT as f64).cos()) as f32
+            })
+            .collect();
+
+        Ok(Self {
+            n_mels,
+            filters: filters.to_vec(),
+            filter_bounds,
+            window,
+            fft: RealFft::new(N_FFT),
+            frame: vec![0.0; N_FFT],
+            power: vec![0.0; N_FREQS],
+        })
+    }
+
+    /// Log-mel spectrogram of `pcm`, padded or truncated to 30 seconds.
+    /// Returns `(n_mels, N_FRAMES)` values, row-major.
+    pub fn compute(&mut self, pcm: &[f32]) -> Vec<f32> {
+        let mut mel = vec![0.0f32; self.n_mels * N_FRAMES];
+        let mut column = vec![0.0f32; self.n_mels];
+        for frame_idx in 0..N_FRAMES {
+            self.column(pcm, frame_idx * HOP_LENGTH, &mut column);
+            scatter_column(&mut mel, frame_idx, &column);
+        }
+
+        normalize(&mut mel);
+        mel
+    }
+
+    /// Like `compute`, for a rolling window that starts `start_sample`
+    /// samples into the stream `cache` belongs to. Frames that lie wholly
+    /// inside audio seen by an earlier call are reused, so only newly pushed
+    /// samples go through the FFT. Once the window has slid, its first
+    /// frames keep the audio before it rather than reflecting. Falls back to
+    /// `compute` when the window is not hop-aligned.
+    pub fn compute_streaming(
+        &mut self,
+        cache: &mut MelCache,
+        pcm: &[f32],
+        start_sample: usize,
+    ) -> Vec<f32> {
+        if !start_sample.is_multiple_of(HOP_LENGTH) {
+            cache.clear();
+            return self.compute(pcm);
+        }
+        let pcm = &pcm[..pcm.len().min(N_SAMPLES)];
+        let start_frame = start_sample / HOP_LENGTH;
+
+        // Frames of another model's size are no use
+        if cache.n_mels != self.n_mels {
+            cache.clear();
+            cache.n_mels = self.n_mels;
+        }
+        // Drop frames that slid out of the window; a window that jumps
+        // outside the cache means a new stream
+        let cached = cache.frames.len() / self.n_mels;
+        if start_frame < cache.start || start_frame > cache.start + cached {
+            cache.clear();
+        } else {
+            let stale = start_frame - cache.start;
+            cache.frames.drain(..stale * self.n_mels);
+        }
+        cache.start = start_frame;
+
+        // Extend the cache with frames whose samples are all available
+        let complete = if pcm.len() >= N_FFT / 2 {
+            ((pcm.len() - N_FFT / 2) / HOP_LENGTH + 1).min(N_FRAMES)
+        } else {
+            0
+        };
+        let mut column = vec![0.0f32; self.n_mels];
+        for frame_idx in cache.frames.len() / self.n_mels..complete {
+            self.column(pcm, frame_idx * HOP_LENGTH, &mut column);
+            cache.frames.extend_from_slice(&column);
+        }
+
+        let mut mel = vec![0.0f32; self.n_mels * N_FRAMES];
+        for (frame_idx, cached) in cache.frames.chunks_exact(self.n_mels).enumerate() {
+            scatter_column(&mut mel, frame_idx, cached);
+        }
+        // The tail overlaps padding and is recomputed until more audio arrives
+        for frame_idx in complete..N_FRAMES {
+            self.column(pcm, frame_idx * HOP_LENGTH, &mut column);
+            scatter_column(&mut mel, frame_idx, &column);
+        }
+
+        normalize(&mut mel);
+        mel
+    }
+
+    /// Log10 mel energies of the frame centred on sample `center` of `pcm`,
+    /// before normalisation.
+    fn column(&mut self, pcm: &[f32], center: usize, out: &mut [f32]) {
+        let half = N_FFT / 2;
+        let len = pcm.len().min(N_SAMPLES);
+        if center >= len + half && center + half <= N_SAMPLES {
+            // Entirely in the zero padding
+            out.fill(1e-10f32.log10());
+            return;
+        }
+
+        if center >= half && center + half <= len {
+            self.frame.copy_from_slice(&pcm[center - half..center + half]);
+        } else {
+            // Zero-pad to 30 seconds, then reflect at both ends
+            for (i, s) in self.frame.iter_mut().enumerate() {
+                let pos = (center + i) as isize - half as isize;
+                let pos = if pos < 0 {
+                    -pos
+                } else if pos >= N_SAMPLES as isize {
+                    2 * (N_SAMPLES as isize - 1) - pos
+                } else {
+                    pos
+                } as usize;
+                *s = pcm.get(pos).copied().unwrap_or(0.0);
+            }
+        }
+
+        for (s, w) in self.frame.iter_mut().zip(&self.window) {
+            *s *= w;
+        }
+        self.fft.power_spectrum(&self.frame, &mut self.power);
+
+        for (m_idx, out) in out.iter_mut().enumerate() {
+            let (lo, hi) = self.filter_bounds[m_idx];
+            let row = &self.filters[m_idx * N_FREQS..(m_idx + 1) * N_FREQS];
+            let sum: f32 = row[lo..hi]
+                .iter()
+                .zip(&self.power[lo..hi])
+                .map(|(w, p)| w * p)
+                .sum();
+            *out = sum.max(1e-10).log10();
+        }
+    }
+}
+
+fn scatter_column(mel: &mut [f32], frame_idx: usize, column: &[f32]) {
+    for (m_idx, &v) in column.iter().enumerate() {
+        mel[m_idx * N_FRAMES + frame_idx] = v;
+    }
+}
+
+/// Clamps to 8 below the peak and scales into Whisper's input range.
+fn normalize(mel: &mut [f32]) {
+    let max_val = mel.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let min_val = max_val - 8.0;
+    for v in mel.iter_mut() {
+        *v = (v.max(min_val) + 4.0) / 4.0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+
+    fn add(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re + other.re,
+            im: self.im + other.im,
+        }
+    }
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re - other.re,
+            im: self.im - other.im,
+        }
+    }
+
+    fn conj(self) -> Complex {
+        Complex {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+/// Real FFT of even length `n`, computed as a complex FFT of length `n / 2`
+/// over the interleaved samples.
+struct RealFft {
+    n: usize,
+    fft: ComplexFft,
+    // e^{-2πik/n} for the split step
+    twiddles: Vec<Complex>,
+    packed: Vec<Complex>,
+    spectrum: Vec<Complex>,
+}
+
+impl RealFft {
+    fn new(n: usize) -> Self {
+        let half = n / 2;
+        Self {
+            n,
+            fft: ComplexFft::new(half),
+            twiddles: twiddles(n, half + 1),
+            packed: vec![Complex::default(); half],
+            spectrum: vec![Complex::default(); half],
+        }
+    }
+
+    /// Writes `|X[k]|²` for `k` in `0..=n/2`.
+    fn power_spectrum(&mut self, input: &[f32], power: &mut [f32]) {
+        let half = self.n / 2;
+        for (z, pair) in self.packed.iter_mut().zip(input.as_chunks::<2>().0) {
+            *z = Complex {
+                re: pair[0],
+                im: pair[1],
+            };
+        }
+        self.fft.process(&self.packed, &mut self.spectrum);
+
+        for (k, p) in power.iter_mut().enumerate().take(half + 1) {
+            let z_k = self.spectrum[k % half];
+            let z_rev = self.spectrum[(half - k) % half].conj();
+            let even = z_k.add(z_rev);
+            // (Z[k] - conj(Z[n/2-k])) / i
+            let diff = z_k.sub(z_rev);
+            let odd = Complex {
+                re: diff.im,
+                im: -diff.re,
+            };
+            let x = even.add(self.twiddles[k].mul(odd));
+            *p = 0.25 * x.norm_sqr();
+        }
+    }
+}
+
+/// Mixed-radix decimation-in-time complex FFT, after KISS FFT.
+struct ComplexFft {
+    n: usize,
+    // (radix, remaining length) at each stage
+    factors: Vec<(usize, usize)>,
+    twiddles: Vec<Complex>,
+    scratch: Vec<Complex>,
+}
+
+impl ComplexFft {
+    fn new(n: usize) -> Self {
+        let mut factors = Vec::new();
+        let mut rest = n;
+        let mut p = 4;
+        while rest > 1 {
+            while !rest.is_multiple_of(p) {
+                p = match p {
+                    4 => 2,
+                    2 => 3,
+                    _ => p + 2,
+                };
+                if p * p > rest {
+                    p = rest;
+                }
+            }
+            rest /= p;
+            factors.push((p, rest));
+        }
+        let max_radix = factors.iter().map(|&(p, _)| p).max().unwrap_or(1);
+        Self {
+            n,
+            factors,
+            twiddles: twiddles(n, n),
+            scratch: vec![Complex::default(); max_radix],
+        }
+    }
+
+    fn process(&mut self, input: &[Complex], output: &mut [Complex]) {
+        if self.n == 1 {
+            output[0] = input[0];
+            return;
+        }
+        Self::work(
+            output,
+            input,
+            0,
+            1,
+            &self.factors,
+            &self.twiddles,
+            &mut self.scratch,
+        );
+    }
+
+    fn work(
+        out: &mut [Complex],
+        input: &[Complex],
+        offset: usize,
+        fstride: usize,
+        factors: &[(usize, usize)],
+        twiddles: &[Complex],
+        scratch: &mut [Complex],
+    ) {
+        let (p, m) = factors[0];
+        if m == 1 {
+            for (j, o) in out.iter_mut().enumerate().take(p) {
+                *o = input[offset + j * fstride];
+            }
+        } else {
+            for (j, chunk) in out.chunks_exact_mut(m).enumerate().take(p) {
+                Self::work(
+                    chunk,
+                    input,
+                    offset + j * fstride,
+                    fstride * p,
+                    &factors[1..],
+                    twiddles,
+                    scratch,
+                );
+            }
+        }
+
+        // Generic radix-p butterfly
+        let n = twiddles.len();
+        for u in 0..m {
+            for q1 in 0..p {
+                scratch[q1] = out[u + q1 * m];
+            }
+            for q1 in 0..p {
+                let k = u + q1 * m;
+                let mut acc = scratch[0];
+                let mut tw_idx = 0;
+                for &s in &scratch[1..p] {
+                    tw_idx = (tw_idx + fstride * k) % n;
+                    acc = acc.add(s.mul(twiddles[tw_idx]));
+                }
+                out[k] = acc;
+            }
+        }
+    }
+}
+
+/// `count` values of e^{-2πik/n}.
+fn twiddles(n: usize, count: usize) -> Vec<Complex> {
+    (0..count)
+        .map(|k| {
+            let angle = -2.0 * std::f64::consts::PI * k as f64 / n as f64;
+            Complex {
+                re: angle.cos() as f32,
+                im: angle.sin() as f32,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive_power_spectrum(input: &[f32]) -> Vec<f64> {
+        let n = input.len();
+        (0..=n / 2)
+            .map(|k| {
+                let (mut re, mut im) = (0.0f64, 0.0f64);
+                for (i, &x) in input.iter().enumerate() {
+                    let angle = -2.0 * std::f64::consts::PI * (k * i % n) as f64 / n as f64;
+                    re += x as f64 * angle.cos();
+                    im += x as f64 * angle.sin();
+                }
+                re * re + im * im
+            })
+            .collect()
+    }
+
+    // Deterministic noise in [-1, 1)
+    fn noise(len: usize) -> Vec<f32> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    fn assert_fft_matches(n: usize) {
+        let input = noise(n);
+        let mut power = vec![0.0f32; n / 2 + 1];
+        RealFft::new(n).power_spectrum(&input, &mut power);
+        let expected = naive_power_spectrum(&input);
+        let peak = expected.iter().cloned().fold(0.0, f64::max);
+        for (k, (&got, &want)) in power.iter().zip(&expected).enumerate() {
+            assert!(
+                (got as f64 - want).abs() < 1e-5 * peak,
+                "n={n}, bin {k}: {got} vs {want}"
+            );
+        }
+    }
+
+    #[test]
+    fn fft_matches_naive_dft() {
+        // N_FFT factors as 4 * 2 * 5 * 5 after halving
+        assert_fft_matches(N_FFT);
+        // 231 = 3 * 7 * 11 takes the generic radix
+        assert_fft_matches(462);
+        assert_fft_matches(16);
+    }
+
+    // tests/fixtures/log_mel_reference.txt is written by the script next to
+    // it, a float64 transcription of the reference `log_mel_spectrogram`,
+    // for 0.5 s of a 440 Hz tone plus a 3 kHz tone fading in
+    #[test]
+    fn compute_matches_reference_log_mel() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/log_mel_reference.txt");
+        let fixture = std::fs::read_to_string(path).unwrap();
+        let mut lines = fixture.lines();
+        let floor: f32 = lines.next().unwrap().rsplit(' ').next().unwrap().parse().unwrap();
+        let expected: Vec<Vec<f32>> = lines
+            .map(|line| line.split(' ').map(|v| v.parse().unwrap()).collect())
+            .collect();
+        assert_eq!(expected.len(), 80);
+
+        let n = SAMPLE_RATE / 2;
+        let pcm: Vec<f32> = (0..n)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let tau = 2.0 * std::f64::consts::PI;
+                let fade = i as f64 / n as f64;
+                (0.3 * (tau * 440.0 * t).sin() + 0.2 * (tau * 3000.0 * t + 0.5).sin() * fade)
+                    as f32
+            })
+            .collect();
+        let mut spectrogram = MelSpectrogram::new(80, &mel_filters(80)).unwrap();
+        let mel = spectrogram.compute(&pcm);
+        assert_eq!(mel.len(), 80 * N_FRAMES);
+
+        for (bin, row) in mel.as_chunks::<N_FRAMES>().0.iter().enumerate() {
+            for (frame, &got) in row.iter().enumerate() {
+                let want = expected[bin].get(frame).copied().unwrap_or(floor);
+                assert!(
+                    (got - want).abs() < 1e-4,
+                    "frame {frame}, mel {bin}: {got} vs {want}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn streaming_matches_compute_while_growing() {
+        let pcm = noise(3 * SAMPLE_RATE);
+        let mut spectrogram = MelSpectrogram::new(80, &mel_filters(80)).unwrap();
+        let mut cache = MelCache::new();
+        for len in (1000..=pcm.len()).step_by(3100) {
+            let streamed = spectrogram.compute_streaming(&mut cache, &pcm[..len], 0);
+            assert_eq!(streamed, spectrogram.compute(&pcm[..len]), "len {len}");
+        }
+    }
+
+    #[test]
+    fn streaming_reuses_audio_before_a_slid_window() {
+        let pcm = noise(3 * SAMPLE_RATE);
+        let mut spectrogram = MelSpectrogram::new(80, &mel_filters(80)).unwrap();
+        let mut cache = MelCache::new();
+        spectrogram.compute_streaming(&mut cache, &pcm[..2 * SAMPLE_RATE], 0);
+
+        let start = 10 * HOP_LENGTH;
+        let streamed = spectrogram.compute_streaming(&mut cache, &pcm[start..], start);
+        let whole = spectrogram.compute(&pcm[start..]);
+        // Only frames whose window reaches before the start differ
+        let first_full = N_FFT / 2 / HOP_LENGTH + 1;
+        for bin in 0..80 {
+            let row = bin * N_FRAMES;
+            assert_eq!(
+                streamed[row + first_full..row + N_FRAMES],
+                whole[row + first_full..row + N_FRAMES]
+            );
+        }
+    }
+
+    #[test]
+    fn streaming_keeps_streams_apart() {
+        let a = noise(SAMPLE_RATE);
+        let b: Vec<f32> = a.iter().map(|x| 0.1 * x).collect();
+        let mut spectrogram = MelSpectrogram::new(80, &mel_filters(80)).unwrap();
+        let (mut cache_a, mut cache_b) = (MelCache::new(), MelCache::new());
+        for len in [4000, 8000, SAMPLE_RATE] {
+            let mel_a = spectrogram.compute_streaming(&mut cache_a, &a[..len], 0);
+            let mel_b = spectrogram.compute_streaming(&mut cache_b, &b[..len], 0);
+            assert_eq!(mel_a, spectrogram.compute(&a[..len]));
+            assert_eq!(mel_b, spectrogram.compute(&b[..len]));
+        }
+    }
+
+    fn nonzero(row: &[f32]) -> Vec<(usize, f32)> {
+        row.iter().enumerate().filter(|(_, w)| **w != 0.0).map(|(i, &w)| (i, w)).collect()
+    }
+
+    fn assert_weights(row: &[f32], expected: &[(usize, f32)]) {
+        for &(bin, want) in expected {
+            assert!((row[bin] - want).abs() < 1e-7, "bin {bin}: {} vs {want}", row[bin]);
+        }
+    }
+
+    // Values from librosa.filters.mel(sr=16000, n_fft=400, n_mels=...)
+    #[test]
+    fn mel_filters_match_librosa() {
+        let filters = mel_filters(80);
+        assert_eq!(filters.len(), 80 * N_FREQS);
+        let rows = filters.as_chunks::<N_FREQS>().0;
+        assert_eq!(nonzero(&rows[0]).len(), 1);
+        assert_weights(&rows[0], &[(1, 0.02486259)]);
+        assert_weights(&rows[1], &[(1, 0.00199082), (2, 0.02287177)]);
+        assert_eq!(nonzero(&rows[40]).iter().map(|(i, _)| *i).collect::<Vec<_>>(), [42, 43, 44]);
+        assert_weights(&rows[40], &[(42, 0.00541111), (43, 0.01473557), (44, 0.00651819)]);
+        let last = nonzero(&rows[79]);
+        assert_eq!((last.len(), last[0].0, last[13].0), (14, 186, 199));
+        assert_weights(&rows[79], &[(186, 0.00036674), (199, 0.00044876)]);
+
+        let filters = mel_filters(128);
+        assert_eq!(filters.len(), 128 * N_FREQS);
+        let rows = filters.as_chunks::<N_FREQS>().0;
+        assert_weights(&rows[0], &[(1, 0.01237399)]);
+        let last = nonzero(&rows[127]);
+        assert_eq!((last.len(), last[0].0, last[8].0), (9, 191, 199));
+        assert_weights(&rows[127], &[(191, 0.0004757), (199, 0.00111428)]);
+    }
+}
@@ -0,0 +1,162 @@
+use std::io::Cursor;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::resample;
+
+const SAMPLE_RATE: u32 = 16000;
+
+/// Decodes a WAV (16, 24 or 32-bit PCM, or float), FLAC, Ogg/Vorbis or MP3
+/// file into the 16 kHz mono PCM `WhisperDecoder` expects. The format is
+/// detected from the contents; `extension` (e.g. `"mp3"`) only breaks ties.
+pub fn decode_audio_file(bytes: Vec<u8>, extension: Option<&str>) -> Result<Vec<f32>, String> {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Audio format error: {e}"))?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| "Audio format error: no audio track".to_string())?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Audio codec error: {e}"))?;
+
+    let mut mono = Vec::new();
+    let mut sample_rate = track.codec_params.sample_rate;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            // A chained stream changing its parameters; keep what we have
+            Err(Error::ResetRequired) => break,
+            Err(e) => return Err(format!("Audio read error: {e}")),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame; skip it as players do
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Audio decode error: {e}")),
+        };
+
+        let spec = *decoded.spec();
+        sample_rate = Some(spec.rate);
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        downmix(buffer.samples(), spec.channels.count(), &mut mono);
+    }
+
+    let sample_rate =
+        sample_rate.ok_or_else(|| "Audio format error: unknown sample rate".to_string())?;
+    Ok(resample::resample(&mono, sample_rate, SAMPLE_RATE))
+}
+
+// Averages interleaved channels into `mono`
+fn downmix(interleaved: &[f32], channels: usize, mono: &mut Vec<f32>) {
+    if channels <= 1 {
+        mono.extend_from_slice(interleaved);
+        return;
+    }
+    mono.extend(
+        interleaved
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every fixture is 0.25 s of a 440 Hz sine at half scale
+    const FIXTURE_SAMPLES: usize = 4000;
+
+    fn fixture(name: &str) -> Vec<f32> {
+        let path = format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"));
+        let bytes = std::fs::read(&path).unwrap();
+        let extension = name.rsplit('.').next();
+        decode_audio_file(bytes, extension).unwrap()
+    }
+
+    fn sine(amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * 440.0 * i as f64 / SAMPLE_RATE as f64;
+                amplitude * phase.sin() as f32
+            })
+            .collect()
+    }
+
+    fn max_error(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| (x - y).abs()).fold(0.0, f32::max)
+    }
+
+    // RMS of the middle half, away from codec and resampler edges
+    fn middle_rms(pcm: &[f32]) -> f32 {
+        let middle = &pcm[pcm.len() / 4..pcm.len() * 3 / 4];
+        (middle.iter().map(|x| x * x).sum::<f32>() / middle.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn decodes_wav_at_every_bit_depth() {
+        let expected = sine(0.5, FIXTURE_SAMPLES);
+        for (name, tolerance) in [
+            ("pcm24_mono_16k.wav", 1e-6),
+            ("pcm32_mono_16k.wav", 1e-6),
+            ("float32_mono_16k.wav", 1e-6),
+        ] {
+            let pcm = fixture(name);
+            assert_eq!(pcm.len(), FIXTURE_SAMPLES, "{name}");
+            assert!(max_error(&pcm, &expected) < tolerance, "{name}");
+        }
+    }
+
+    #[test]
+    fn averages_stereo_channels() {
+        // The sine is on the left channel only
+        let pcm = fixture("pcm16_stereo_16k.wav");
+        assert_eq!(pcm.len(), FIXTURE_SAMPLES);
+        assert!(max_error(&pcm, &sine(0.25, FIXTURE_SAMPLES)) < 1e-4);
+    }
+
+    #[test]
+    fn resamples_flac_to_16khz() {
+        let pcm = fixture("pcm16_mono_44k.flac");
+        assert_eq!(pcm.len(), FIXTURE_SAMPLES);
+        let expected = sine(0.5, FIXTURE_SAMPLES);
+        assert!(max_error(&pcm[200..3800], &expected[200..3800]) < 1e-3);
+    }
+
+    #[test]
+    fn decodes_lossy_formats() {
+        // Vorbis and MP3 pad the end, MP3 also delays the start
+        for name in ["vorbis_stereo_48k.ogg", "mono_44k.mp3"] {
+            let pcm = fixture(name);
+            assert!((FIXTURE_SAMPLES..FIXTURE_SAMPLES + 600).contains(&pcm.len()), "{name}");
+            let rms = middle_rms(&pcm);
+            assert!((rms - 0.5 / 2f32.sqrt()).abs() < 0.03, "{name}: {rms}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_data() {
+        assert!(decode_audio_file(b"not audio at all".to_vec(), None).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::agreement::{self, LocalAgreement, TimedWord};
+use crate::audio::dsp::{DspChain, DspOptions};
+use crate::audio::MelCache;
+use crate::resample::Resampler;
+use crate::streaming::StreamingBuffer;
+use crate::timestamps::Segment;
+
+const SAMPLE_RATE: u32 = 16000;
+
+/// Side of a call an input carries, when the microphone and the remote
+/// audio are transcribed separately.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Me,
+    Remote,
+}
+
+impl Channel {
+    pub fn from_id(id: &str) -> Result<Self, String> {
+        match id {
+            "me" => Ok(Self::Me),
+            "remote" => Ok(Self::Remote),
+            _ => Err(format!("Unsupported channel: {id}")),
+        }
+    }
+}
+
+/// Everything one live input needs between capture and committed text.
+pub struct ChannelStream {
+    pub resampler: Resampler,
+    pub dsp: DspChain,
+    pub streaming: StreamingBuffer,
+    pub agreement: LocalAgreement,
+    pub mel_cache: MelCache,
+}
+
+impl ChannelStream {
+    fn new(dsp: DspOptions) -> Self {
+        Self {
+            resampler: Resampler::new(SAMPLE_RATE, SAMPLE_RATE),
+            dsp: DspChain::new(dsp),
+            streaming: StreamingBuffer::new(),
+            agreement: LocalAgreement::new(),
+            mel_cache: MelCache::new(),
+        }
+    }
+}
+
+/// The untagged stream used for single or mixed capture, and one per
+/// `Channel`. All share a timeline, since their audio is captured together.
+pub struct ChannelStreams {
+    dsp: DspOptions,
+    mixed: ChannelStream,
+    me: ChannelStream,
+    remote: ChannelStream,
+}
+
+impl ChannelStreams {
+    pub fn new(dsp: DspOptions) -> Self {
+        Self {
+            mixed: ChannelStream::new(dsp.clone()),
+            me: ChannelStream::new(dsp.clone()),
+            remote: ChannelStream::new(dsp.clone()),
+            dsp,
+        }
+    }
+
+    pub fn get_mut(&mut self, channel: Option<Channel>) -> &mut ChannelStream {
+        match channel {
+            None => &mut self.mixed,
+            Some(Channel::Me) => &mut self.me,
+            Some(Channel::Remote) => &mut self.remote,
+        }
+    }
+
+    /// Replaces a stream with a fresh one, so that a new recording starts
+    /// a new timeline with nothing of the last one's audio, words or filter
+    /// history.
+    pub fn reset(&mut self, channel: Option<Channel>) {
+        *self.get_mut(channel) = ChannelStream::new(self.dsp.clone());
+    }
+
+    pub fn dsp_options(&self) -> &DspOptions {
+        &self.dsp
+    }
+
+    /// Replaces every stream's pre-processing chain, restarting its filters.
+    pub fn set_dsp(&mut self, dsp: DspOptions) {
+        for stream in [&mut self.mixed, &mut self.me, &mut self.remote] {
+            stream.dsp = DspChain::new(dsp.clone());
+        }
+        self.dsp = dsp;
+    }
+}
+
+/// Committed `words` as segments split at `boundaries`, each tagged with
+/// the `channel` it was heard on so inputs can be interleaved by time.
+pub fn tagged_segments(
+    words: &[TimedWord],
+    boundaries: &[f64],
+    channel: Option<Channel>,
+) -> Vec<Segment> {
+    let mut segments = agreement::words_to_segments(words, boundaries);
+    for segment in &mut segments {
+        segment.channel = channel;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_ids_round_trip() {
+        for channel in [Channel::Me, Channel::Remote] {
+            let id = serde_json::to_value(channel).unwrap();
+            assert_eq!(Channel::from_id(id.as_str().unwrap()), Ok(channel));
+        }
+        for id in ["", "Me", "mixed", "left"] {
+            assert!(Channel::from_id(id).is_err(), "{id}");
+        }
+    }
+
+    #[test]
+    fn channels_keep_their_own_audio() {
+        let mut streams = ChannelStreams::new(DspOptions::default());
+        streams.get_mut(Some(Channel::Me)).streaming.push(&[0.1; 16000]);
+        streams.get_mut(Some(Channel::Remote)).streaming.push(&[-0.2; 8000]);
+
+        assert_eq!(streams.get_mut(None).streaming.duration_seconds(), 0.0);
+        let me = streams.get_mut(Some(Channel::Me)).streaming.get_final_chunk(true);
+        assert_eq!(me, [0.1; 16000]);
+        let remote = streams.get_mut(Some(Channel::Remote)).streaming.get_final_chunk(true);
+        assert_eq!(remote, [-0.2; 8000]);
+    }
+
+    #[test]
+    fn segments_interleave_by_time() {
+        let word = |text: &str, start: f64| {
+            TimedWord::new(text.into(), vec![1], vec![0.0], start, start + 0.3)
+        };
+        // Each input's words, split at pauses into segments
+        let me = [word(" hello", 0.0), word(" there", 0.4), word(" fine", 4.0)];
+        let remote = [word(" hi", 2.0), word(" how", 2.4), word(" bye", 6.0)];
+
+        let mut segments = tagged_segments(&remote, &[], Some(Channel::Remote));
+        segments.extend(tagged_segments(&me, &[], Some(Channel::Me)));
+        segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+        let turns: Vec<_> =
+            segments.iter().map(|s| (s.channel, s.text.as_str(), s.start)).collect();
+        assert_eq!(
+            turns,
+            [
+                (Some(Channel::Me), "hello there", 0.0),
+                (Some(Channel::Remote), "hi how", 2.0),
+                (Some(Channel::Me), "fine", 4.0),
+                (Some(Channel::Remote), "bye", 6.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_starts_a_new_timeline() {
+        let mut streams = ChannelStreams::new(DspOptions::default());
+        let stream = streams.get_mut(Some(Channel::Me));
+        stream.streaming.push(&[0.1; 3 * 16000]);
+        stream.streaming.trim_before(2.0);
+        let word = TimedWord::new(" hi".into(), vec![1], vec![0.0], 2.5, 2.7);
+        stream.agreement.insert(vec![word]);
+        assert_eq!(stream.streaming.start_seconds(), 2.0);
+
+        streams.reset(Some(Channel::Me));
+        // The next recording's segments are offset from 0 again
+        let stream = streams.get_mut(Some(Channel::Me));
+        assert_eq!(stream.streaming.start_seconds(), 0.0);
+        assert_eq!(stream.streaming.duration_seconds(), 0.0);
+        assert!(stream.agreement.tentative().is_empty());
+        assert_eq!(stream.agreement.last_committed_end(), 0.0);
+    }
+}
@@ -1,73 +1,811 @@
-use candle_core::{Device, Tensor};
+use candle_core::{DType, Device, Tensor};
 use candle_nn::VarBuilder;
+use candle_transformers::generation::{LogitsProcessor, Sampling};
 use candle_transformers::models::whisper::{self as m, Config};
+use candle_transformers::quantized_var_builder::VarBuilder as QVarBuilder;
 use serde::{Serialize, Deserialize};
 use tokenizers::Tokenizer;
 
+use crate::agreement::{self, TimedWord};
+use crate::alignment;
 use crate::audio;
+use crate::decoding::{self, DecodePass, DecodingOptions, DecodingStrategy, LogitBias};
 use crate::languages;
+use crate::model::{self, CrossKvCache, KvCache};
+use crate::safeguards::{self, Safeguard, SafeguardOptions};
+use crate::timestamps::{self, Segment, TimestampTokens};
 
-const SAMPLE_RATE: usize = 16000;
+// Number of ranked language candidates kept on a result
+const MAX_LANGUAGE_CANDIDATES: usize = 5;
+// Half of the decoder's 448-token context, as in the reference implementation
+const MAX_SAMPLE_LEN: usize = 224;
+const SAMPLING_SEED: u64 = 299792458;
+// Long-form passes sampled above this temperature don't prompt the next window
+const PROMPT_RESET_TEMPERATURE: f64 = 0.5;
+const GGUF_MAGIC: &[u8] = b"GGUF";
+// Vocabulary size from which a model is multilingual; English-only `.en`
+// models have one language token fewer
+const MULTILINGUAL_VOCAB_SIZE: usize = 51865;
+/// Error returned when a decode is stopped by its cancel check.
+pub const CANCELLED: &str = "Decode cancelled";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LanguageCandidate {
+    pub language: String,
+    pub probability: f32,
+}
+
+/// How the spoken language is chosen before decoding.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum LanguageSelection {
+    /// Detect among every language the model knows.
+    #[default]
+    Auto,
+    /// Skip detection and decode as this language.
+    Fixed { language: String },
+    /// Detect, but only among these languages.
+    AllowList { languages: Vec<String> },
+}
+
+impl LanguageSelection {
+    /// Builds a selection from the UI's source language, where `"auto"` means
+    /// detection, restricted to `allowed` when it is non-empty.
+    pub fn from_code(language: &str, allowed: &[String]) -> Result<Self, String> {
+        if language != "auto" {
+            Self::validate(language)?;
+            return Ok(Self::Fixed {
+                language: language.to_string(),
+            });
+        }
+        if allowed.is_empty() {
+            return Ok(Self::Auto);
+        }
+        for code in allowed {
+            Self::validate(code)?;
+        }
+        Ok(Self::AllowList {
+            languages: allowed.to_vec(),
+        })
+    }
+
+    fn validate(code: &str) -> Result<(), String> {
+        match languages::language_name(code) {
+            Some(_) => Ok(()),
+            None => Err(format!("Unsupported language: {code}")),
+        }
+    }
+}
+
+/// What Whisper produces from the speech.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Task {
+    /// Text in the spoken language.
+    #[default]
+    Transcribe,
+    /// English text, whatever the spoken language.
+    Translate,
+}
+
+impl Task {
+    pub fn from_id(id: &str) -> Result<Self, String> {
+        match id {
+            "transcribe" => Ok(Self::Transcribe),
+            "translate" => Ok(Self::Translate),
+            _ => Err(format!("Unsupported task: {id}")),
+        }
+    }
+
+    fn token(&self) -> &'static str {
+        match self {
+            Self::Transcribe => "<|transcribe|>",
+            Self::Translate => "<|translate|>",
+        }
+    }
+}
+
+/// How safetensors weights are held in memory. Computation is always F32;
+/// half-precision linear weights are upcast as they are applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightPrecision {
+    #[default]
+    F32,
+    /// Roughly half the memory, at some cost in decoding speed.
+    F16,
+    /// As F16, with F32's range but less precision.
+    Bf16,
+}
+
+impl WeightPrecision {
+    pub fn from_id(id: &str) -> Result<Self, String> {
+        match id {
+            "f32" => Ok(Self::F32),
+            "f16" => Ok(Self::F16),
+            "bf16" => Ok(Self::Bf16),
+            _ => Err(format!("Unsupported weight precision: {id}")),
+        }
+    }
+
+    fn dtype(&self) -> DType {
+        match self {
+            Self::F32 => DType::F32,
+            Self::F16 => DType::F16,
+            Self::Bf16 => DType::BF16,
+        }
+    }
+}
+
+/// Vocabulary hints for names and jargon Whisper would otherwise misspell.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PromptOptions {
+    /// Text treated as if spoken just before the audio, e.g. a glossary.
+    /// Kept ahead of the carried-over transcript in every prompt.
+    pub initial_prompt: String,
+    /// Words whose tokens get `hotword_bias` added to their logits.
+    pub hotwords: Vec<String>,
+    pub hotword_bias: f32,
+}
+
+/// Per-session decoding settings.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TranscribeOptions {
+    pub language: LanguageSelection,
+    pub task: Task,
+    /// Predict timestamp tokens and return timed segments.
+    pub timestamps: bool,
+    /// Time each word by aligning its tokens to the audio through
+    /// cross-attention, rather than spreading segment times evenly.
+    pub word_timestamps: bool,
+    pub decoding: DecodingOptions,
+    pub prompt: PromptOptions,
+    pub safeguards: SafeguardOptions,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TranscriptionResult {
     pub text: String,
     pub language: Option<String>,
+    pub language_probability: Option<f32>,
+    pub language_candidates: Vec<LanguageCandidate>,
+    /// Timed segments, in seconds from the start of the audio. Empty unless
+    /// timestamps are enabled.
+    pub segments: Vec<Segment>,
+    /// Words with their token log-probabilities and confidence. Times are
+    /// spread over each segment, or over the whole window without
+    /// timestamps.
+    pub words: Vec<TimedWord>,
+    pub avg_logprob: f64,
+    pub compression_ratio: f64,
+    /// Temperature of the pass that produced the text; above the first
+    /// pass's when fallback kicked in.
+    pub temperature: f64,
+    /// Probability of `<|nospeech|>` at the start of the transcript.
+    pub no_speech_probability: f32,
+    /// Safeguards that removed text from this result.
+    pub safeguards: Vec<Safeguard>,
+}
+
+impl TranscriptionResult {
+    /// Shifts segment and word times by `seconds`, e.g. to place a window
+    /// on the stream's timeline.
+    pub fn offset_segments(&mut self, seconds: f64) {
+        for segment in &mut self.segments {
+            segment.start += seconds;
+            segment.end += seconds;
+        }
+        for word in &mut self.words {
+            word.start += seconds;
+            word.end += seconds;
+        }
+    }
+}
+
+/// What a decoding pass is conditioned on, and how its logits are shaped.
+struct DecodeContext<'a> {
+    language: &'a str,
+    task: Task,
+    timestamps: bool,
+    /// Text tokens of earlier audio, fed after `<|startofprev|>`.
+    previous: &'a [u32],
+    hotwords: &'a LogitBias,
+    safeguards: &'a SafeguardOptions,
+}
+
+/// Special token ids from the model's config.json, consulted when the
+/// tokenizer lacks the token.
+#[derive(Deserialize, Default)]
+struct ConfigTokens {
+    decoder_start_token_id: Option<u32>,
+    eos_token_id: Option<u32>,
+}
+
+/// Control token ids, which shift between the English-only, multilingual
+/// and large-v3 vocabularies.
+#[derive(Debug, Clone, Copy)]
+struct SpecialTokens {
+    sot: u32,
+    sot_prev: Option<u32>,
+    transcribe: Option<u32>,
+    translate: Option<u32>,
+    no_speech: Option<u32>,
+    timestamps: TimestampTokens,
+}
+
+impl SpecialTokens {
+    fn new(tokenizer: &Tokenizer, config: &ConfigTokens) -> Result<Self, String> {
+        let id = |token: &str| tokenizer.token_to_id(token);
+        let required = |token: &str, fallback: Option<u32>| {
+            id(token)
+                .or(fallback)
+                .ok_or_else(|| format!("Tokenizer error: no {token} token"))
+        };
+        let no_timestamps = required("<|notimestamps|>", None)?;
+        Ok(Self {
+            sot: required("<|startoftranscript|>", config.decoder_start_token_id)?,
+            sot_prev: id("<|startofprev|>"),
+            transcribe: id(Task::Transcribe.token()),
+            translate: id(Task::Translate.token()),
+            no_speech: id("<|nospeech|>").or_else(|| id("<|nocaptions|>")),
+            timestamps: TimestampTokens {
+                eot: required("<|endoftext|>", config.eos_token_id)?,
+                no_timestamps,
+                // Timestamps follow `<|notimestamps|>` in every vocabulary
+                timestamp_begin: id("<|0.00|>").unwrap_or(no_timestamps + 1),
+            },
+        })
+    }
+
+    fn task(&self, task: Task) -> Option<u32> {
+        match task {
+            Task::Transcribe => self.transcribe,
+            Task::Translate => self.translate,
+        }
+    }
 }
 
 pub struct WhisperDecoder {
-    model: m::model::Whisper,
+    model: model::Whisper,
     tokenizer: Tokenizer,
     config: Config,
-    mel_filters: Vec<f32>,
+    mel: audio::MelSpectrogram,
     device: Device,
+    special: SpecialTokens,
+    /// False for English-only models, whose prompt has no language or task.
+    multilingual: bool,
+    /// Tokens masked at every step when `suppress_tokens` is on.
+    suppress_tokens: Vec<u32>,
+    /// Tokens masked at the first step when `suppress_blank` is on.
+    blank_tokens: Vec<u32>,
+    /// (layer, head) pairs whose cross-attention tracks the audio position.
+    alignment_heads: Vec<(usize, usize)>,
+    quantized: bool,
+    /// Polled before each encoder pass and decoding step.
+    cancel_check: Option<Box<dyn Fn() -> bool>>,
 }
 
 impl WhisperDecoder {
+    /// Loads any Whisper checkpoint in the Hugging Face layout: tiny to
+    /// large-v3, large-v3-turbo, distil-whisper and the English-only `.en`
+    /// variants. Weights come from safetensors or from quantized GGUF (e.g.
+    /// q4_0, q5_1 or q8_0), told apart by the file's magic bytes.
+    /// Safetensors are stored at `precision` and `model_bytes` shrinks as
+    /// they are read, so loading peaks near the larger of the file and the
+    /// loaded weights rather than their sum; GGUF keeps its own
+    /// quantization.
     pub fn new(
-        model_bytes: &[u8],
+        model_bytes: Vec<u8>,
         tokenizer_json: &str,
         config_json: &str,
-        mel_bytes: &[u8],
+        precision: WeightPrecision,
     ) -> Result<Self, String> {
         let device = Device::Cpu;
 
         let config: Config =
             serde_json::from_str(config_json).map_err(|e| format!("Config parse error: {e}"))?;
 
+        let config_tokens: ConfigTokens =
+            serde_json::from_str(config_json).map_err(|e| format!("Config parse error: {e}"))?;
+
         let tokenizer = Tokenizer::from_bytes(tokenizer_json.as_bytes())
             .map_err(|e| format!("Tokenizer error: {e}"))?;
+        let special = SpecialTokens::new(&tokenizer, &config_tokens)?;
+        let multilingual = config.vocab_size >= MULTILINGUAL_VOCAB_SIZE;
 
-        let vb = VarBuilder::from_buffered_safetensors(
-            model_bytes.to_vec(),
-            candle_core::DType::F32,
-            &device,
-        )
-        .map_err(|e| format!("VarBuilder error: {e}"))?;
+        let quantized = model_bytes.starts_with(GGUF_MAGIC);
+        let vb = if quantized {
+            let vb = QVarBuilder::from_gguf_buffer(&model_bytes, &device)
+                .map_err(|e| format!("GGUF error: {e}"))?;
+            model::Weights::Quantized(vb)
+        } else {
+            let tensors = model::load_safetensors(model_bytes, precision.dtype(), &device)
+                .map_err(|e| format!("Safetensors error: {e}"))?;
+            let vb = VarBuilder::from_tensors(tensors, precision.dtype(), &device);
+            model::Weights::Full(vb)
+        };
 
         let model =
-            m::model::Whisper::load(&vb, config.clone()).map_err(|e| format!("Model load error: {e}"))?;
+            model::Whisper::load(&vb, config.clone()).map_err(|e| format!("Model load error: {e}"))?;
+        // Weights the model converted go with the builder, before the
+        // decoder allocates
+        drop(vb);
 
-        // Parse mel filter bytes (f32 little-endian)
-        let mel_filters: Vec<f32> = mel_bytes
-            .chunks_exact(4)
-            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-            .collect();
+        // 80 bins for most checkpoints, 128 for large-v3 and turbo
+        let mel_filters = audio::mel_filters(config.num_mel_bins);
+        let mel = audio::MelSpectrogram::new(config.num_mel_bins, &mel_filters)?;
+
+        // The config's non-speech symbols, plus control tokens that only
+        // belong in the prompt
+        let mut suppress_tokens = config.suppress_tokens.clone();
+        suppress_tokens.extend(
+            [
+                "<|transcribe|>",
+                "<|translate|>",
+                "<|startoftranscript|>",
+                "<|startofprev|>",
+                "<|startoflm|>",
+                "<|nospeech|>",
+                "<|nocaptions|>",
+            ]
+            .iter()
+            .filter_map(|t| tokenizer.token_to_id(t)),
+        );
+        let mut blank_tokens = tokenizer
+            .encode(" ", false)
+            .map_err(|e| format!("Tokenizer error: {e}"))?
+            .get_ids()
+            .to_vec();
+        blank_tokens.push(special.timestamps.eot);
+        let alignment_heads =
+            alignment::default_heads(config.decoder_layers, config.decoder_attention_heads);
 
         Ok(Self {
             model,
             tokenizer,
             config,
-            mel_filters,
+            mel,
             device,
+            special,
+            multilingual,
+            suppress_tokens,
+            blank_tokens,
+            alignment_heads,
+            quantized,
+            cancel_check: None,
+        })
+    }
+
+    /// False for English-only `.en` models, which ignore the language and
+    /// task settings.
+    pub fn is_multilingual(&self) -> bool {
+        self.multilingual
+    }
+
+    /// Whether the weights came from a quantized GGUF file.
+    pub fn is_quantized(&self) -> bool {
+        self.quantized
+    }
+
+    /// Replaces the default alignment heads with a model's own, e.g. the
+    /// `alignment_heads` of its generation config.
+    pub fn set_alignment_heads(&mut self, heads: Vec<(usize, usize)>) -> Result<(), String> {
+        let (n_layers, n_heads) = (self.config.decoder_layers, self.config.decoder_attention_heads);
+        if let Some((layer, head)) = heads.iter().find(|&&(l, h)| l >= n_layers || h >= n_heads) {
+            return Err(format!("Invalid alignment head: layer {layer}, head {head}"));
+        }
+        self.alignment_heads = heads;
+        Ok(())
+    }
+
+    /// Sets a check that stops decodes with `CANCELLED` once it returns
+    /// true. It is polled between steps, so a decode ends within one step.
+    pub fn set_cancel_check(&mut self, check: Option<Box<dyn Fn() -> bool>>) {
+        self.cancel_check = check;
+    }
+
+    fn check_cancelled(&self) -> Result<(), String> {
+        match &self.cancel_check {
+            Some(check) if check() => Err(CANCELLED.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn transcribe(
+        &mut self,
+        pcm: &[f32],
+        options: &TranscribeOptions,
+    ) -> Result<TranscriptionResult, String> {
+        let mel = self.mel.compute(pcm);
+        self.transcribe_mel(mel, pcm.len(), &[], options)
+    }
+
+    /// Transcribes a rolling window that starts `start_sample` samples into
+    /// a stream, reusing mel frames `mel_cache` holds from the stream's
+    /// earlier windows. `previous` holds text tokens of already transcribed
+    /// audio before the window.
+    pub fn transcribe_stream(
+        &mut self,
+        pcm: &[f32],
+        start_sample: usize,
+        mel_cache: &mut audio::MelCache,
+        previous: &[u32],
+        options: &TranscribeOptions,
+    ) -> Result<TranscriptionResult, String> {
+        let mel = self.mel.compute_streaming(mel_cache, pcm, start_sample);
+        self.transcribe_mel(mel, pcm.len(), previous, options)
+    }
+
+    fn transcribe_mel(
+        &mut self,
+        mel: Vec<f32>,
+        n_samples: usize,
+        previous: &[u32],
+        options: &TranscribeOptions,
+    ) -> Result<TranscriptionResult, String> {
+        let cross_kv = self.encode(mel)?;
+        let (language, language_probability, mut candidates) =
+            self.choose_language(&cross_kv, &options.language)?;
+
+        // Decode
+        let (previous, hotwords) = self.prompt_context(&options.prompt, previous)?;
+        let ctx = DecodeContext {
+            language: &language,
+            task: options.task,
+            timestamps: options.timestamps,
+            previous: &previous,
+            hotwords: &hotwords,
+            safeguards: &options.safeguards,
+        };
+        let no_speech_probability = self.no_speech_probability(&cross_kv, &ctx)?;
+        let pass = self.decode_with_fallback(&cross_kv, &ctx, &options.decoding)?;
+
+        let mut applied: Vec<Safeguard> = pass
+            .repetition_cut
+            .clone()
+            .map(|removed| Safeguard::RepetitionCut { removed })
+            .into_iter()
+            .collect();
+        let (text, segments) = if self.is_no_speech(no_speech_probability, &pass, options) {
+            applied.push(Safeguard::NoSpeech {
+                probability: no_speech_probability,
+            });
+            (String::new(), Vec::new())
+        } else {
+            // Without timestamp tokens this is one segment spanning the window
+            let ts_tokens = self.timestamp_tokens();
+            let duration = n_samples.min(m::N_SAMPLES) as f64 / m::SAMPLE_RATE as f64;
+            let mut segments = timestamps::split_segments(
+                &pass.tokens,
+                &pass.token_logprobs,
+                &ts_tokens,
+                duration,
+                |t| self.decode_text(t),
+            )?;
+            let blocked = options.safeguards.filter_segments(&mut segments);
+            let text = if blocked.is_empty() {
+                pass.text.clone()
+            } else {
+                segments.iter().map(|s| s.text.as_str()).collect::<Vec<_>>().join(" ")
+            };
+            applied.extend(blocked);
+            (text, segments)
+        };
+        let token_times = if options.word_timestamps && !segments.is_empty() {
+            let text_tokens: Vec<u32> =
+                segments.iter().flat_map(|s| s.tokens.iter().copied()).collect();
+            Some(self.token_times(&cross_kv, &ctx, &text_tokens, n_samples)?)
+        } else {
+            None
+        };
+        let words = self.timed_words(&segments, token_times.as_deref())?;
+        let segments = if options.timestamps {
+            segments
+        } else {
+            Vec::new()
+        };
+
+        candidates.truncate(MAX_LANGUAGE_CANDIDATES);
+        Ok(TranscriptionResult {
+            text,
+            language: Some(language),
+            language_probability,
+            language_candidates: candidates,
+            segments,
+            words,
+            avg_logprob: pass.avg_logprob,
+            compression_ratio: pass.compression_ratio,
+            temperature: pass.temperature,
+            no_speech_probability,
+            safeguards: applied,
         })
     }
 
-    pub fn transcribe(&mut self, pcm: &[f32]) -> Result<TranscriptionResult, String> {
-        // Convert PCM to mel spectrogram
-        let mel = audio::pcm_to_mel(&self.config, pcm, &self.mel_filters)
-            .map_err(|e| format!("Mel conversion error: {e}"))?;
+    /// Transcribes audio of any length by walking it in 30-second windows.
+    /// Each window starts at the last complete timestamp of the one before
+    /// and is conditioned on the text decoded so far, so segments neither
+    /// overlap nor lose speech cut at a window edge. The language is chosen
+    /// on the first window and kept for the rest.
+    pub fn transcribe_long(
+        &mut self,
+        pcm: &[f32],
+        options: &TranscribeOptions,
+    ) -> Result<Vec<Segment>, String> {
+        let ts_tokens = self.timestamp_tokens();
+        let mut language: Option<String> = None;
+        let mut history: Vec<u32> = Vec::new();
+        let mut segments = Vec::new();
+        let mut seek = 0;
+
+        while seek < pcm.len() {
+            let window = &pcm[seek..pcm.len().min(seek + m::N_SAMPLES)];
+            let window_duration = window.len() as f64 / m::SAMPLE_RATE as f64;
+            let time_offset = seek as f64 / m::SAMPLE_RATE as f64;
+
+            let mel = self.mel.compute(window);
+            let cross_kv = self.encode(mel)?;
+            let window_language = match &language {
+                Some(language) => language.clone(),
+                None => self.choose_language(&cross_kv, &options.language)?.0,
+            };
+            language = Some(window_language.clone());
+
+            let (previous, hotwords) = self.prompt_context(&options.prompt, &history)?;
+            let ctx = DecodeContext {
+                language: &window_language,
+                task: options.task,
+                timestamps: true,
+                previous: &previous,
+                hotwords: &hotwords,
+                safeguards: &options.safeguards,
+            };
+            let no_speech_probability = self.no_speech_probability(&cross_kv, &ctx)?;
+            let pass = self.decode_with_fallback(&cross_kv, &ctx, &options.decoding)?;
+
+            // A silent window yields no segments and doesn't prompt the next
+            if self.is_no_speech(no_speech_probability, &pass, options) {
+                seek += window.len();
+                continue;
+            }
+
+            // Text after the last complete timestamp is left for the next
+            // window
+            let advance = timestamps::seek_offset(&pass.tokens, &ts_tokens, window_duration);
+            let mut window_segments = timestamps::split_segments(
+                &pass.tokens,
+                &pass.token_logprobs,
+                &ts_tokens,
+                window_duration,
+                |t| self.decode_text(t),
+            )?;
+            window_segments.retain(|segment| segment.start < advance);
+            options.safeguards.filter_segments(&mut window_segments);
+
+            // Text from a high-temperature pass is a poor prompt
+            if pass.temperature > PROMPT_RESET_TEMPERATURE {
+                history.clear();
+            } else {
+                for segment in &window_segments {
+                    history.extend_from_slice(&segment.tokens);
+                }
+            }
+
+            for mut segment in window_segments {
+                segment.start += time_offset;
+                segment.end += time_offset;
+                segments.push(segment);
+            }
+
+            let advance_samples =
+                ((advance * m::SAMPLE_RATE as f64).round() as usize).min(window.len());
+            seek += if advance_samples == 0 {
+                window.len()
+            } else {
+                advance_samples
+            };
+        }
+
+        Ok(segments)
+    }
+
+    /// Splits segments into words at tokens that start with a space, or after
+    /// each complete character in scripts written without spaces. Each word's
+    /// confidence comes from its tokens' log-probabilities. Times come from
+    /// `token_times`, the start of every token across the segments followed
+    /// by the end of the last, or are otherwise spread evenly over each
+    /// segment's tokens.
+    pub fn timed_words(
+        &self,
+        segments: &[Segment],
+        token_times: Option<&[f64]>,
+    ) -> Result<Vec<TimedWord>, String> {
+        let decode = |tokens: &[u32]| {
+            self.tokenizer
+                .decode(tokens, true)
+                .map_err(|e| format!("Decode error: {e}"))
+        };
+
+        let mut words = Vec::new();
+        let mut offset = 0;
+        for segment in segments {
+            if segment.tokens.is_empty() {
+                continue;
+            }
+            let step = (segment.end - segment.start) / segment.tokens.len() as f64;
+            let time = |i: usize| match token_times.and_then(|t| t.get(offset + i)) {
+                Some(&time) => time,
+                None if i == segment.tokens.len() => segment.end,
+                None => segment.start + i as f64 * step,
+            };
+
+            let logprob = |i: usize| segment.token_logprobs.get(i).copied().unwrap_or(0.0);
+
+            let mut word: Vec<u32> = Vec::new();
+            let mut word_start = 0;
+            for (i, &token) in segment.tokens.iter().enumerate() {
+                if !word.is_empty() {
+                    let text = decode(&word)?;
+                    let starts_word = decode(&[token])?.starts_with(char::is_whitespace);
+                    let complete = !text.contains(char::REPLACEMENT_CHARACTER)
+                        && text.chars().last().is_some_and(agreement::is_unspaced_script);
+                    if starts_word || complete {
+                        words.push(TimedWord::new(
+                            text,
+                            std::mem::take(&mut word),
+                            (word_start..i).map(logprob).collect(),
+                            time(word_start),
+                            time(i),
+                        ));
+                        word_start = i;
+                    }
+                }
+                word.push(token);
+            }
+            words.push(TimedWord::new(
+                decode(&word)?,
+                word,
+                (word_start..segment.tokens.len()).map(logprob).collect(),
+                time(word_start),
+                time(segment.tokens.len()),
+            ));
+            offset += segment.tokens.len();
+        }
+        Ok(words)
+    }
+
+    /// Builds the previous-text prompt from the initial prompt followed by as
+    /// much of the carried-over `history` as fits in half the decoder
+    /// context, and tokenizes the hotwords.
+    fn prompt_context(
+        &self,
+        options: &PromptOptions,
+        history: &[u32],
+    ) -> Result<(Vec<u32>, LogitBias), String> {
+        let max_prompt_len = self.config.max_target_positions / 2 - 1;
+
+        let initial = options.initial_prompt.trim();
+        let mut previous = if initial.is_empty() {
+            Vec::new()
+        } else {
+            let mut tokens = self.encode_text(&format!(" {initial}"))?;
+            tokens.truncate(max_prompt_len);
+            tokens
+        };
+        let room = max_prompt_len - previous.len();
+        previous.extend_from_slice(&history[history.len().saturating_sub(room)..]);
+
+        // Bias each word both mid-sentence and at the start of a segment
+        let mut sequences = Vec::new();
+        for word in options.hotwords.iter().map(|w| w.trim()).filter(|w| !w.is_empty()) {
+            sequences.push(self.encode_text(&format!(" {word}"))?);
+            sequences.push(self.encode_text(word)?);
+        }
+        Ok((previous, LogitBias::new(sequences, options.hotword_bias)))
+    }
+
+    /// Probability of `<|nospeech|>` in place of the language token, given
+    /// the context's previous text.
+    fn no_speech_probability(
+        &self,
+        cross_kv: &CrossKvCache,
+        ctx: &DecodeContext,
+    ) -> Result<f32, String> {
+        // The check costs a decoder pass over the prompt, so skip it when off
+        let Some(no_speech_token) = self
+            .special
+            .no_speech
+            .filter(|_| ctx.safeguards.no_speech_threshold.is_some())
+        else {
+            return Ok(0.0);
+        };
+
+        let prompt = self.prompt_tokens(ctx);
+        let sot_index = prompt.iter().position(|&t| t == self.special.sot).unwrap_or(0);
+        let mut cache = self.model.decoder.empty_cache();
+        let logits = self
+            .next_token_logits(&prompt[..=sot_index], cross_kv, &mut cache)?
+            .to_vec1::<f32>()
+            .map_err(|e| format!("To vec error: {e}"))?;
+        Ok(decoding::log_softmax(&logits)
+            .get(no_speech_token as usize)
+            .map_or(0.0, |logprob| logprob.exp()))
+    }
+
+    fn is_no_speech(
+        &self,
+        no_speech_probability: f32,
+        pass: &DecodePass,
+        options: &TranscribeOptions,
+    ) -> bool {
+        let logprob_threshold = options
+            .decoding
+            .fallback
+            .as_ref()
+            .and_then(|f| f.logprob_threshold);
+        options
+            .safeguards
+            .is_no_speech(no_speech_probability, pass.avg_logprob, logprob_threshold)
+    }
+
+    /// Start time of each of `text_tokens` within the window, followed by
+    /// the end of the last, from dynamic time warping over the alignment
+    /// heads' cross-attention.
+    fn token_times(
+        &self,
+        cross_kv: &CrossKvCache,
+        ctx: &DecodeContext,
+        text_tokens: &[u32],
+        n_samples: usize,
+    ) -> Result<Vec<f64>, String> {
+        // Aligned without previous text, as the reference implementation does
+        let sot_ctx = DecodeContext {
+            timestamps: false,
+            previous: &[],
+            ..*ctx
+        };
+        let mut tokens = self.prompt_tokens(&sot_ctx);
+        // From `<|notimestamps|>` on, each position predicts a text token or EOT
+        let first_row = tokens.len() - 1;
+        tokens.extend_from_slice(text_tokens);
+        tokens.push(self.timestamp_tokens().eot);
+        let n_tokens = tokens.len();
+
+        let token_tensor = Tensor::new(tokens.as_slice(), &self.device)
+            .map_err(|e| format!("Token tensor error: {e}"))?
+            .unsqueeze(0)
+            .map_err(|e| format!("Unsqueeze error: {e}"))?;
+        let cross_qk = self
+            .model
+            .decoder
+            .cross_attention_qk(&token_tensor, cross_kv, &self.alignment_heads)
+            .map_err(|e| format!("Alignment error: {e}"))?;
+
+        // Encoder frames past the end of the audio only see padding
+        let n_frames = (n_samples.min(m::N_SAMPLES) / m::HOP_LENGTH / 2).max(1);
+        let heads = cross_qk
+            .iter()
+            .map(|qk| qk.narrow(1, 0, n_frames)?.flatten_all()?.to_vec1::<f32>())
+            .collect::<candle_core::Result<Vec<_>>>()
+            .map_err(|e| format!("Alignment error: {e}"))?;
+
+        Ok(alignment::token_times(&heads, n_tokens, n_frames, first_row..n_tokens - 1))
+    }
+
+    fn encode_text(&self, text: &str) -> Result<Vec<u32>, String> {
+        Ok(self
+            .tokenizer
+            .encode(text, false)
+            .map_err(|e| format!("Tokenizer error: {e}"))?
+            .get_ids()
+            .to_vec())
+    }
 
+    /// Runs the encoder and precomputes the cross-attention keys and values
+    /// shared by every decoding step.
+    fn encode(&self, mel: Vec<f32>) -> Result<CrossKvCache, String> {
+        self.check_cancelled()?;
         let mel_len = mel.len() / self.config.num_mel_bins;
         let mel_tensor = Tensor::from_vec(
             mel,
@@ -76,100 +814,422 @@ impl WhisperDecoder {
         )
         .map_err(|e| format!("Tensor error: {e}"))?;
 
-        // Encode
         let encoder_output = self
             .model
             .encoder
-            .forward(&mel_tensor, true)
+            .forward(&mel_tensor)
             .map_err(|e| format!("Encoder error: {e}"))?;
+        self.model
+            .decoder
+            .cross_kv(&encoder_output)
+            .map_err(|e| format!("Cross-attention error: {e}"))
+    }
+
+    /// Returns the language to decode as, its detection probability, and the
+    /// ranked candidates. Detection is skipped when the user pinned one.
+    fn choose_language(
+        &self,
+        cross_kv: &CrossKvCache,
+        selection: &LanguageSelection,
+    ) -> Result<(String, Option<f32>, Vec<LanguageCandidate>), String> {
+        if !self.multilingual {
+            return Ok(("en".to_string(), None, Vec::new()));
+        }
+        let candidates = match selection {
+            LanguageSelection::Fixed { language } => {
+                // e.g. Cantonese, which only the large-v3 vocabulary has
+                if self.tokenizer.token_to_id(&languages::language_token(language)).is_none() {
+                    return Err(format!("Unsupported language for this model: {language}"));
+                }
+                return Ok((language.clone(), None, Vec::new()));
+            }
+            LanguageSelection::Auto => self.detect_language(cross_kv, None)?,
+            LanguageSelection::AllowList { languages } => {
+                self.detect_language(cross_kv, Some(languages))?
+            }
+        };
+        let top = candidates
+            .first()
+            .ok_or_else(|| "Language detection error: no language tokens".to_string())?;
+        Ok((top.language.clone(), Some(top.probability), candidates))
+    }
 
-        // Detect language
-        let language = self.detect_language(&encoder_output)?;
+    /// Runs the decoder once on `<|startoftranscript|>` and ranks the language
+    /// tokens by their softmax probability, most likely first. With `allowed`,
+    /// the softmax only covers those languages.
+    fn detect_language(
+        &self,
+        cross_kv: &CrossKvCache,
+        allowed: Option<&[String]>,
+    ) -> Result<Vec<LanguageCandidate>, String> {
+        // Only languages the tokenizer knows about take part in the softmax
+        let (codes, lang_tokens): (Vec<&str>, Vec<u32>) = languages::WHISPER_LANGUAGES
+            .iter()
+            .filter(|(code, _)| allowed.is_none_or(|a| a.iter().any(|c| c == code)))
+            .filter_map(|(code, _)| {
+                self.tokenizer
+                    .token_to_id(&languages::language_token(code))
+                    .map(|id| (*code, id))
+            })
+            .unzip();
+        if lang_tokens.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        // Decode
-        let text = self.greedy_decode(&encoder_output, &language)?;
+        let mut cache = self.model.decoder.empty_cache();
+        let logits = self.next_token_logits(&[self.special.sot], cross_kv, &mut cache)?;
+        let lang_ids = Tensor::new(lang_tokens.as_slice(), &self.device)
+            .map_err(|e| format!("Token tensor error: {e}"))?;
+        let probs = candle_nn::ops::softmax_last_dim(
+            &logits
+                .index_select(&lang_ids, 0)
+                .map_err(|e| format!("Index select error: {e}"))?,
+        )
+        .map_err(|e| format!("Softmax error: {e}"))?
+        .to_vec1::<f32>()
+        .map_err(|e| format!("To vec error: {e}"))?;
 
-        Ok(TranscriptionResult {
-            text,
-            language: Some(language),
-        })
+        let mut candidates: Vec<LanguageCandidate> = codes
+            .into_iter()
+            .zip(probs)
+            .map(|(code, probability)| LanguageCandidate {
+                language: code.to_string(),
+                probability,
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+
+        Ok(candidates)
     }
 
-    fn detect_language(&self, encoder_output: &Tensor) -> Result<String, String> {
-        // For simplicity, use the SOT token detection approach
-        // In a full implementation, we'd use the language detection head
-        Ok("en".to_string())
+    /// Feeds `tokens` through the decoder after those already in `cache` and
+    /// returns the vocabulary logits for the position after the last token.
+    fn next_token_logits(
+        &self,
+        tokens: &[u32],
+        cross_kv: &CrossKvCache,
+        cache: &mut KvCache,
+    ) -> Result<Tensor, String> {
+        let token_tensor = Tensor::new(tokens, &self.device)
+            .map_err(|e| format!("Token tensor error: {e}"))?
+            .unsqueeze(0)
+            .map_err(|e| format!("Unsqueeze error: {e}"))?;
+
+        let hidden = self
+            .model
+            .decoder
+            .forward(&token_tensor, cross_kv, cache)
+            .map_err(|e| format!("Decoder error: {e}"))?;
+
+        let logits = self
+            .model
+            .decoder
+            .final_linear(&hidden)
+            .map_err(|e| format!("Final linear error: {e}"))?;
+
+        let (_, seq_len, _) = logits.dims3().map_err(|e| format!("Dims error: {e}"))?;
+        logits
+            .get_on_dim(1, seq_len - 1)
+            .map_err(|e| format!("Get last error: {e}"))?
+            .squeeze(0)
+            .map_err(|e| format!("Squeeze error: {e}"))
     }
 
-    fn greedy_decode(
-        &mut self,
-        encoder_output: &Tensor,
-        language: &str,
-    ) -> Result<String, String> {
-        let sot_token = self
-            .tokenizer
-            .token_to_id("<|startoftranscript|>")
-            .unwrap_or(50258);
-        let eot_token = self
-            .tokenizer
-            .token_to_id("<|endoftext|>")
-            .unwrap_or(50257);
-        let transcribe_token = self
-            .tokenizer
-            .token_to_id("<|transcribe|>")
-            .unwrap_or(50359);
-        let notimestamps_token = self
-            .tokenizer
-            .token_to_id("<|notimestamps|>")
-            .unwrap_or(50363);
+    fn timestamp_tokens(&self) -> TimestampTokens {
+        self.special.timestamps
+    }
 
-        let lang_token = self
+    fn decode_text(&self, tokens: &[u32]) -> Result<String, String> {
+        let text = self
             .tokenizer
-            .token_to_id(&format!("<|{language}|>"))
-            .unwrap_or(50259);
+            .decode(tokens, true)
+            .map_err(|e| format!("Decode error: {e}"))?;
+        Ok(text.trim().to_string())
+    }
 
-        let mut tokens = vec![sot_token, lang_token, transcribe_token, notimestamps_token];
-        let mut result_tokens = Vec::new();
+    /// Decodes with the configured strategy, then resamples at each higher
+    /// fallback temperature for as long as the result fails the thresholds.
+    fn decode_with_fallback(
+        &self,
+        cross_kv: &CrossKvCache,
+        ctx: &DecodeContext,
+        options: &DecodingOptions,
+    ) -> Result<DecodePass, String> {
+        let strategy = &options.strategy;
+        let mut pass = self.decode(cross_kv, ctx, strategy)?;
 
-        for _ in 0..224 {
-            let token_tensor = Tensor::new(
-                tokens.as_slice(),
-                &self.device,
-            )
-            .map_err(|e| format!("Token tensor error: {e}"))?
-            .unsqueeze(0)
-            .map_err(|e| format!("Unsqueeze error: {e}"))?;
+        if let Some(fallback) = &options.fallback {
+            for temperature in fallback.retry_temperatures(strategy.temperature()) {
+                if !fallback.needs_retry(&pass) {
+                    break;
+                }
+                let retry = DecodingStrategy::Sampling { temperature };
+                pass = self.decode(cross_kv, ctx, &retry)?;
+            }
+        }
+
+        Ok(pass)
+    }
+
+    fn decode(
+        &self,
+        cross_kv: &CrossKvCache,
+        ctx: &DecodeContext,
+        strategy: &DecodingStrategy,
+    ) -> Result<DecodePass, String> {
+        let prompt = self.prompt_tokens(ctx);
+        let (mut tokens, mut token_logprobs, sum_logprob) = match strategy {
+            DecodingStrategy::Greedy => self.sample_decode(cross_kv, prompt, ctx, None)?,
+            DecodingStrategy::Sampling { temperature } => {
+                let mut sampler = LogitsProcessor::from_sampling(
+                    SAMPLING_SEED,
+                    Sampling::All {
+                        temperature: *temperature,
+                    },
+                );
+                self.sample_decode(cross_kv, prompt, ctx, Some(&mut sampler))?
+            }
+            DecodingStrategy::BeamSearch {
+                beam_size,
+                patience,
+            } => self.beam_search(cross_kv, prompt, ctx, *beam_size, *patience)?,
+        };
+
+        let ts_tokens = self.timestamp_tokens();
+        let text_of = |tokens: &[u32]| {
+            let text_tokens: Vec<u32> = tokens
+                .iter()
+                .copied()
+                .filter(|&t| !ts_tokens.is_timestamp(t))
+                .collect();
+            self.decode_text(&text_tokens)
+        };
+        let mut text = text_of(&tokens)?;
+        let avg_logprob = sum_logprob / (tokens.len() + 1) as f64;
+        // Scored before the cut, so a looping pass still triggers fallback
+        let compression_ratio = decoding::compression_ratio(&text);
+
+        let safeguards = ctx.safeguards;
+        let mut repetition_cut = None;
+        let loop_end = safeguards::find_loop(&tokens, safeguards.max_ngram, safeguards.min_repeats);
+        if let Some(end) = loop_end {
+            repetition_cut = Some(text_of(&tokens.split_off(end))?);
+            token_logprobs.truncate(end);
+            text = text_of(&tokens)?;
+        }
+
+        Ok(DecodePass {
+            avg_logprob,
+            compression_ratio,
+            temperature: strategy.temperature(),
+            tokens,
+            token_logprobs,
+            text,
+            repetition_cut,
+        })
+    }
+
+    /// The start-of-transcript sequence, preceded by the previous text
+    /// tokens under `<|startofprev|>` when there are any. English-only
+    /// models take no language or task token.
+    fn prompt_tokens(&self, ctx: &DecodeContext) -> Vec<u32> {
+        let mut tokens = Vec::new();
+        if let Some(sot_prev) = self.special.sot_prev.filter(|_| !ctx.previous.is_empty()) {
+            tokens.push(sot_prev);
+            tokens.extend_from_slice(ctx.previous);
+        }
+        tokens.push(self.special.sot);
+        if self.multilingual {
+            tokens.extend(self.tokenizer.token_to_id(&languages::language_token(ctx.language)));
+            tokens.extend(self.special.task(ctx.task));
+        }
+        if !ctx.timestamps {
+            tokens.push(self.timestamp_tokens().no_timestamps);
+        }
+        tokens
+    }
 
-            let logits = self
-                .model
-                .decoder
-                .forward(&token_tensor, encoder_output, true)
-                .map_err(|e| format!("Decoder error: {e}"))?;
+    /// Logits for the token after `tokens`, with hotwords boosted, suppressed
+    /// tokens masked and, when timestamps are on, the timestamp rules applied.
+    /// Only the tokens not yet in `cache` are fed.
+    fn step_logits(
+        &self,
+        tokens: &[u32],
+        prompt_len: usize,
+        cross_kv: &CrossKvCache,
+        cache: &mut KvCache,
+        ctx: &DecodeContext,
+    ) -> Result<Vec<f32>, String> {
+        self.check_cancelled()?;
+        let mut logits = self
+            .next_token_logits(&tokens[cache.len()..], cross_kv, cache)?
+            .to_vec1::<f32>()
+            .map_err(|e| format!("To vec error: {e}"))?;
+        ctx.hotwords.apply(&mut logits, &tokens[prompt_len..]);
+        if ctx.safeguards.suppress_tokens {
+            mask(&mut logits, &self.suppress_tokens);
+        }
+        if ctx.safeguards.suppress_blank && tokens.len() == prompt_len {
+            mask(&mut logits, &self.blank_tokens);
+        }
+        if ctx.timestamps {
+            let ts_tokens = self.timestamp_tokens();
+            timestamps::apply_timestamp_rules(&mut logits, &tokens[prompt_len..], &ts_tokens);
+        }
+        Ok(logits)
+    }
+
+    /// Decodes a single sequence, taking the argmax or, with a `sampler`,
+    /// sampling. Returns the sampled tokens, their log-probabilities and
+    /// the sum of those and of the EOT that ended them.
+    fn sample_decode(
+        &self,
+        cross_kv: &CrossKvCache,
+        mut tokens: Vec<u32>,
+        ctx: &DecodeContext,
+        mut sampler: Option<&mut LogitsProcessor>,
+    ) -> Result<(Vec<u32>, Vec<f32>, f64), String> {
+        let eot_token = self.timestamp_tokens().eot;
+        let prompt_len = tokens.len();
+        let mut logprobs = Vec::new();
+        let mut sum_logprob = 0.0;
+        let mut cache = self.model.decoder.empty_cache();
 
-            let (_, seq_len, _) = logits.dims3().map_err(|e| format!("Dims error: {e}"))?;
-            let last_logits = logits
-                .get_on_dim(1, seq_len - 1)
-                .map_err(|e| format!("Get last error: {e}"))?;
+        for _ in 0..MAX_SAMPLE_LEN {
+            // A long prompt leaves less of the decoder's context to fill
+            if tokens.len() >= self.config.max_target_positions {
+                break;
+            }
+            let logits = self.step_logits(&tokens, prompt_len, cross_kv, &mut cache, ctx)?;
 
-            let next_token = last_logits
-                .argmax(candle_core::D::Minus1)
-                .map_err(|e| format!("Argmax error: {e}"))?
-                .to_scalar::<u32>()
-                .map_err(|e| format!("Scalar error: {e}"))?;
+            let next_token = match sampler.as_deref_mut() {
+                Some(sampler) => {
+                    let logits = Tensor::new(logits.as_slice(), &self.device)
+                        .map_err(|e| format!("Tensor error: {e}"))?;
+                    sampler
+                        .sample(&logits)
+                        .map_err(|e| format!("Sampling error: {e}"))?
+                }
+                None => decoding::argmax(&logits),
+            };
 
+            // EOT counts towards the sum, as in beam search, but isn't kept
+            let logprob = decoding::log_softmax(&logits)[next_token as usize];
+            sum_logprob += logprob as f64;
             if next_token == eot_token {
                 break;
             }
 
-            result_tokens.push(next_token);
+            logprobs.push(logprob);
             tokens.push(next_token);
+
+            // Nothing after a loop's first copies is worth decoding
+            let safeguards = ctx.safeguards;
+            if safeguards::ends_in_loop(
+                &tokens[prompt_len..],
+                safeguards.max_ngram,
+                safeguards.min_repeats,
+            ) {
+                break;
+            }
         }
 
-        let text = self
-            .tokenizer
-            .decode(&result_tokens, true)
-            .map_err(|e| format!("Decode error: {e}"))?;
+        Ok((tokens.split_off(prompt_len), logprobs, sum_logprob))
+    }
 
-        Ok(text.trim().to_string())
+    /// Beam search as in the reference implementation: each step expands every
+    /// live beam, keeps the `beam_size` best continuations, and sets aside
+    /// those ending in EOT until `beam_size * patience` have finished. The
+    /// finished sequence with the best length-normalised score wins.
+    fn beam_search(
+        &self,
+        cross_kv: &CrossKvCache,
+        prompt: Vec<u32>,
+        ctx: &DecodeContext,
+        beam_size: usize,
+        patience: f32,
+    ) -> Result<(Vec<u32>, Vec<f32>, f64), String> {
+        let eot_token = self.timestamp_tokens().eot;
+        let prompt_len = prompt.len();
+        let beam_size = beam_size.max(1);
+        let max_finished = ((beam_size as f32 * patience).round() as usize).max(1);
+
+        // Each hypothesis keeps its tokens' log-probabilities, and their sum
+        // including any EOT
+        let mut beams: Vec<(Vec<u32>, Vec<f32>, f64, KvCache)> =
+            vec![(prompt, Vec::new(), 0.0, self.model.decoder.empty_cache())];
+        let mut finished: Vec<(Vec<u32>, Vec<f32>, f64)> = Vec::new();
+
+        for _ in 0..MAX_SAMPLE_LEN {
+            // Live beams are all the same length
+            if beams[0].0.len() >= self.config.max_target_positions {
+                break;
+            }
+            let mut candidates = Vec::new();
+            for (tokens, token_logprobs, sum_logprob, mut cache) in beams.drain(..) {
+                let logits = self.step_logits(&tokens, prompt_len, cross_kv, &mut cache, ctx)?;
+                let logprobs = decoding::log_softmax(&logits);
+                for (token, logprob) in decoding::top_k(&logprobs, beam_size + 1) {
+                    let mut next = tokens.clone();
+                    next.push(token);
+                    let mut next_logprobs = token_logprobs.clone();
+                    next_logprobs.push(logprob);
+                    candidates.push((
+                        next,
+                        next_logprobs,
+                        sum_logprob + logprob as f64,
+                        cache.clone(),
+                    ));
+                }
+            }
+            candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+            for (mut tokens, mut token_logprobs, sum_logprob, cache) in candidates {
+                if tokens.last() == Some(&eot_token) {
+                    if finished.len() < max_finished {
+                        tokens.pop();
+                        token_logprobs.pop();
+                        finished.push((tokens, token_logprobs, sum_logprob));
+                    }
+                } else {
+                    beams.push((tokens, token_logprobs, sum_logprob, cache));
+                    if beams.len() == beam_size {
+                        break;
+                    }
+                }
+            }
+
+            if finished.len() >= max_finished || beams.is_empty() {
+                break;
+            }
+        }
+
+        // Unfinished beams still count if nothing reached EOT in time
+        if finished.is_empty() {
+            finished = beams
+                .into_iter()
+                .map(|(tokens, token_logprobs, sum_logprob, _)| {
+                    (tokens, token_logprobs, sum_logprob)
+                })
+                .collect();
+        }
+
+        let score = |(tokens, _, sum_logprob): &(Vec<u32>, Vec<f32>, f64)| {
+            sum_logprob / (tokens.len() - prompt_len).max(1) as f64
+        };
+        let (mut tokens, token_logprobs, sum_logprob) = finished
+            .into_iter()
+            .max_by(|a, b| score(a).total_cmp(&score(b)))
+            .ok_or_else(|| "Beam search error: no hypotheses".to_string())?;
+
+        Ok((tokens.split_off(prompt_len), token_logprobs, sum_logprob))
+    }
+}
+
+fn mask(logits: &mut [f32], tokens: &[u32]) {
+    for &token in tokens {
+        if let Some(logit) = logits.get_mut(token as usize) {
+            *logit = f32::NEG_INFINITY;
+        }
     }
 }
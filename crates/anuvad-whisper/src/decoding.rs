@@ -0,0 +1,276 @@
+use std::io::Write;
+
+use candle_transformers::models::whisper as m;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+/// How tokens are picked at each decoding step.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DecodingStrategy {
+    /// Take the most likely token.
+    #[default]
+    Greedy,
+    /// Keep `beam_size` hypotheses and stop once `beam_size * patience`
+    /// of them have finished.
+    BeamSearch { beam_size: usize, patience: f32 },
+    /// Sample from the token distribution at `temperature`.
+    Sampling { temperature: f64 },
+}
+
+impl DecodingStrategy {
+    pub fn temperature(&self) -> f64 {
+        match self {
+            Self::Sampling { temperature } => *temperature,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Retry by sampling at higher temperatures when a pass looks like a
+/// repetition loop or a low-confidence guess.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TemperatureFallback {
+    /// Temperatures to try, in order; those not above the first pass's
+    /// temperature are skipped.
+    pub temperatures: Vec<f64>,
+    /// Retry when the text's zlib compression ratio is above this.
+    pub compression_ratio_threshold: Option<f64>,
+    /// Retry when the average token log-probability is below this.
+    pub logprob_threshold: Option<f64>,
+}
+
+impl Default for TemperatureFallback {
+    fn default() -> Self {
+        Self {
+            temperatures: m::TEMPERATURES.to_vec(),
+            compression_ratio_threshold: Some(m::COMPRESSION_RATIO_THRESHOLD),
+            logprob_threshold: Some(m::LOGPROB_THRESHOLD),
+        }
+    }
+}
+
+impl TemperatureFallback {
+    /// Temperatures to retry at after a first pass at `first`.
+    pub fn retry_temperatures(&self, first: f64) -> impl Iterator<Item = f64> + '_ {
+        self.temperatures.iter().copied().filter(move |&t| t > first)
+    }
+
+    pub fn needs_retry(&self, pass: &DecodePass) -> bool {
+        let too_repetitive = self
+            .compression_ratio_threshold
+            .is_some_and(|t| pass.compression_ratio > t);
+        let too_unlikely = self.logprob_threshold.is_some_and(|t| pass.avg_logprob < t);
+        too_repetitive || too_unlikely
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct DecodingOptions {
+    pub strategy: DecodingStrategy,
+    pub fallback: Option<TemperatureFallback>,
+}
+
+/// The outcome of decoding one window at one temperature.
+#[derive(Debug, Clone)]
+pub struct DecodePass {
+    /// Sampled tokens, without the prompt or EOT.
+    pub tokens: Vec<u32>,
+    pub text: String,
+    pub avg_logprob: f64,
+    pub compression_ratio: f64,
+    pub temperature: f64,
+    /// Log-probability of each of `tokens`.
+    pub token_logprobs: Vec<f32>,
+    /// Text removed after a repetition loop, which `tokens` and `text` stop
+    /// short of.
+    pub repetition_cut: Option<String>,
+}
+
+/// Adds a fixed bias to the tokens that start, or continue, any of a set of
+/// token sequences.
+#[derive(Debug, Clone, Default)]
+pub struct LogitBias {
+    sequences: Vec<Vec<u32>>,
+    bias: f32,
+}
+
+impl LogitBias {
+    pub fn new(sequences: Vec<Vec<u32>>, bias: f32) -> Self {
+        let sequences = sequences.into_iter().filter(|s| !s.is_empty()).collect();
+        Self { sequences, bias }
+    }
+
+    /// Boosts the next token of every sequence whose first tokens end
+    /// `sampled`, including the first token of each. A sequence that was
+    /// just sampled in full is left alone, so it isn't pushed to repeat.
+    pub fn apply(&self, logits: &mut [f32], sampled: &[u32]) {
+        if self.bias == 0.0 {
+            return;
+        }
+        for sequence in &self.sequences {
+            if sampled.ends_with(sequence) {
+                continue;
+            }
+            for (matched, &next) in sequence.iter().enumerate() {
+                if sampled.ends_with(&sequence[..matched]) {
+                    if let Some(l) = logits.get_mut(next as usize) {
+                        *l += self.bias;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Ratio of the text's UTF-8 length to its zlib-compressed length. Loops
+/// like "the the the" compress very well.
+pub fn compression_ratio(text: &str) -> f64 {
+    let bytes = text.as_bytes();
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let compressed = encoder
+        .write_all(bytes)
+        .and_then(|_| encoder.finish())
+        .map(|c| c.len())
+        .unwrap_or(bytes.len());
+    bytes.len() as f64 / compressed.max(1) as f64
+}
+
+/// Mean of the values, or 0 when there are none.
+pub fn mean(values: &[f32]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64
+}
+
+pub fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|v| (v - max).exp()).sum::<f32>().ln() + max;
+    logits.iter().map(|v| v - log_sum).collect()
+}
+
+pub fn argmax(values: &[f32]) -> u32 {
+    values
+        .iter()
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |(best_i, best), (i, &v)| {
+            if v > best {
+                (i, v)
+            } else {
+                (best_i, best)
+            }
+        })
+        .0 as u32
+}
+
+/// The `k` highest values as `(index, value)`, best first.
+pub fn top_k(values: &[f32], k: usize) -> Vec<(u32, f32)> {
+    let mut indexed: Vec<(u32, f32)> = values
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| v.is_finite())
+        .map(|(i, &v)| (i as u32, v))
+        .collect();
+    let k = k.min(indexed.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    indexed.select_nth_unstable_by(k - 1, |a, b| b.1.total_cmp(&a.1));
+    indexed.truncate(k);
+    indexed.sort_by(|a, b| b.1.total_cmp(&a.1));
+    indexed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(avg_logprob: f64, compression_ratio: f64) -> DecodePass {
+        DecodePass {
+            tokens: vec![],
+            text: String::new(),
+            avg_logprob,
+            compression_ratio,
+            temperature: 0.0,
+            token_logprobs: vec![],
+            repetition_cut: None,
+        }
+    }
+
+    #[test]
+    fn fallback_retries_at_higher_temperatures() {
+        let fallback = TemperatureFallback::default();
+        let greedy: Vec<f64> = fallback.retry_temperatures(0.0).collect();
+        assert_eq!(greedy, [0.2, 0.4, 0.6, 0.8, 1.0]);
+        let sampled: Vec<f64> = fallback.retry_temperatures(0.5).collect();
+        assert_eq!(sampled, [0.6, 0.8, 1.0]);
+        assert_eq!(fallback.retry_temperatures(1.0).count(), 0);
+    }
+
+    #[test]
+    fn fallback_thresholds() {
+        let fallback = TemperatureFallback::default();
+        assert!(!fallback.needs_retry(&pass(-0.5, 1.5)));
+        // Defaults are a compression ratio of 2.4 and a log-probability of -1
+        assert!(fallback.needs_retry(&pass(-0.5, 2.5)));
+        assert!(fallback.needs_retry(&pass(-1.2, 1.5)));
+        assert!(!fallback.needs_retry(&pass(-1.0, 2.4)));
+
+        let off = TemperatureFallback {
+            compression_ratio_threshold: None,
+            logprob_threshold: None,
+            ..fallback
+        };
+        assert!(!off.needs_retry(&pass(-5.0, 10.0)));
+    }
+
+    #[test]
+    fn repetitive_text_compresses_well() {
+        let looped = "I think that ".repeat(20);
+        let normal = "A quick brown fox jumps over the lazy dog near the river bank, \
+                      then naps under an old oak while two crows argue overhead.";
+        assert!(compression_ratio(&looped) > 2.4);
+        assert!(compression_ratio(normal) < 2.4);
+        assert_eq!(compression_ratio(""), 0.0);
+    }
+
+    #[test]
+    fn top_k_orders_best_first() {
+        let values = [0.1, 3.0, f32::NEG_INFINITY, 2.0, 3.0, -1.0];
+        let top = top_k(&values, 3);
+        assert_eq!(top.iter().map(|t| t.1).collect::<Vec<_>>(), [3.0, 3.0, 2.0]);
+        // Tied values keep both indices
+        let mut tied: Vec<u32> = top[..2].iter().map(|t| t.0).collect();
+        tied.sort();
+        assert_eq!(tied, [1, 4]);
+        assert_eq!(top[2].0, 3);
+        // Masked tokens are never returned
+        assert_eq!(top_k(&values, 10).len(), 5);
+        assert!(top_k(&values, 0).is_empty());
+    }
+
+    fn biased(bias: &LogitBias, sampled: &[u32]) -> Vec<f32> {
+        let mut logits = vec![1.0; 10];
+        bias.apply(&mut logits, sampled);
+        logits
+    }
+
+    #[test]
+    fn bias_follows_the_hotword() {
+        let bias = LogitBias::new(vec![vec![5, 6, 7], vec![]], 2.5);
+        // Only the first token until the hotword starts
+        assert_eq!(biased(&bias, &[1]), [1.0, 1.0, 1.0, 1.0, 1.0, 3.5, 1.0, 1.0, 1.0, 1.0]);
+        // Then its next token too
+        assert_eq!(biased(&bias, &[1, 5, 6]), [1.0, 1.0, 1.0, 1.0, 1.0, 3.5, 1.0, 3.5, 1.0, 1.0]);
+        // Once sampled in full, nothing
+        assert_eq!(biased(&bias, &[5, 6, 7]), [1.0; 10]);
+        assert_eq!(biased(&LogitBias::new(vec![vec![5]], 0.0), &[]), [1.0; 10]);
+    }
+}
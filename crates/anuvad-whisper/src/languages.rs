@@ -114,3 +114,8 @@ pub fn language_code(name: &str) -> Option<&'static str> {
         .find(|(_, n)| *n == name_lower)
         .map(|(code, _)| *code)
 }
+
+/// Special token Whisper uses for a language code, e.g. `<|hi|>`.
+pub fn language_token(code: &str) -> String {
+    format!("<|{code}|>")
+}
@@ -1,10 +1,21 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
 
+pub mod agreement;
+pub mod alignment;
+pub mod audio_file;
+pub mod channels;
 pub mod decoder;
+pub mod decoding;
 pub mod audio;
 pub mod streaming;
 pub mod languages;
+pub mod model;
+pub mod resample;
+pub mod safeguards;
+pub mod speaker;
+pub mod timestamps;
+pub mod vad;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
@@ -13,18 +24,97 @@ pub enum WhisperMessage {
         model_bytes: Vec<u8>,
         tokenizer_json: String,
         config_json: String,
-        mel_bytes: Vec<u8>,
+        #[serde(default)]
+        precision: decoder::WeightPrecision,
+        /// Supplies the model's own alignment heads for word timestamps.
+        #[serde(default)]
+        generation_config_json: Option<String>,
     },
     Transcribe {
         audio: Vec<f32>,
+        /// Rate the audio was captured at; resampled to 16 kHz.
+        sample_rate: u32,
+        /// Input the audio came from, when inputs are transcribed separately.
+        #[serde(default)]
+        channel: Option<channels::Channel>,
     },
-    ModelLoaded,
+    TranscribeLong {
+        audio: Vec<f32>,
+    },
+    TranscribeFile {
+        bytes: Vec<u8>,
+        #[serde(default)]
+        extension: Option<String>,
+    },
+    SetLanguage {
+        language: String,
+        #[serde(default)]
+        allowed_languages: Vec<String>,
+    },
+    SetTask {
+        task: decoder::Task,
+    },
+    SetTimestamps {
+        enabled: bool,
+    },
+    SetWordTimestamps {
+        enabled: bool,
+    },
+    SetDecoding {
+        decoding: decoding::DecodingOptions,
+    },
+    SetPrompt {
+        prompt: decoder::PromptOptions,
+    },
+    SetSafeguards {
+        safeguards: safeguards::SafeguardOptions,
+    },
+    SetDsp {
+        dsp: audio::dsp::DspOptions,
+    },
+    LoadSpeakerModel {
+        model_bytes: Vec<u8>,
+    },
+    SetDiarization {
+        diarization: speaker::DiarizationOptions,
+    },
+    /// Aborts the running decode and drops queued ones. Buffered audio is
+    /// kept for the next decode.
+    Cancel,
+    /// Sent when recording stops: queued decodes are replaced by one final
+    /// decode per channel that commits everything heard.
+    Flush,
+    ModelLoaded {
+        report: Option<LoadReport>,
+    },
+    SpeakerModelLoaded,
     TranscriptionResult {
         text: String,
         language: Option<String>,
+        language_probability: Option<f32>,
+        #[serde(default)]
+        segments: Vec<timestamps::Segment>,
+        #[serde(default)]
+        safeguards: Vec<safeguards::Safeguard>,
+        #[serde(default)]
+        clipping: f32,
+        #[serde(default)]
+        channel: Option<channels::Channel>,
     },
     TranscriptionPartial {
         text: String,
+        #[serde(default)]
+        channel: Option<channels::Channel>,
+    },
+    VoiceActivity {
+        speaking: bool,
+        time: f64,
+        #[serde(default)]
+        channel: Option<channels::Channel>,
+    },
+    /// Transcription requests waiting behind the running decode.
+    QueueDepth {
+        depth: usize,
     },
     Progress {
         percent: f64,
@@ -34,10 +124,37 @@ pub enum WhisperMessage {
     },
 }
 
+/// What loading a model cost, for diagnosing out-of-memory crashes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoadReport {
+    pub precision: decoder::WeightPrecision,
+    pub quantized: bool,
+    pub model_bytes: usize,
+    pub load_ms: f64,
+    /// Size of the worker's linear memory, which only ever grows, so this
+    /// is the peak so far. `None` outside WebAssembly.
+    pub peak_memory_bytes: Option<usize>,
+}
+
 #[wasm_bindgen]
 pub struct WhisperWorker {
     decoder: Option<decoder::WhisperDecoder>,
-    streaming: streaming::StreamingBuffer,
+    streams: channels::ChannelStreams,
+    options: decoder::TranscribeOptions,
+    diarizer: Option<speaker::Diarizer>,
+    diarization: speaker::DiarizationOptions,
+    // Raised by the main thread through shared memory to stop a decode
+    cancel_flag: Option<js_sys::Int32Array>,
+}
+
+const SAMPLE_RATE: u32 = 16000;
+// Once the buffer is this long, audio behind committed segments is dropped
+const TRIM_BUFFER_SECONDS: f64 = 15.0;
+
+impl Default for WhisperWorker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -47,45 +164,370 @@ impl WhisperWorker {
         console_error_panic_hook::set_once();
         Self {
             decoder: None,
-            streaming: streaming::StreamingBuffer::new(),
+            streams: channels::ChannelStreams::new(audio::dsp::DspOptions::default()),
+            options: decoder::TranscribeOptions::default(),
+            diarizer: None,
+            diarization: speaker::DiarizationOptions::default(),
+            cancel_flag: None,
         }
     }
 
+    /// Loads a model, storing safetensors weights at `precision` (`"f32"`,
+    /// `"f16"` or `"bf16"`), and returns a `LoadReport`. The bytes are taken
+    /// by value so the weights are read without another copy. Word timing
+    /// uses the `alignment_heads` of `generation_config_json` when given.
     #[wasm_bindgen]
     pub fn load_model(
         &mut self,
-        model_bytes: &[u8],
+        model_bytes: Vec<u8>,
         tokenizer_json: &str,
         config_json: &str,
-        mel_bytes: &[u8],
+        precision: &str,
+        generation_config_json: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let precision =
+            decoder::WeightPrecision::from_id(precision).map_err(|e| JsValue::from_str(&e))?;
+        // Free the old model first, or both are resident while loading
+        self.decoder = None;
+        let model_len = model_bytes.len();
+        let started = js_sys::Date::now();
+        let mut dec =
+            decoder::WhisperDecoder::new(model_bytes, tokenizer_json, config_json, precision)
+                .map_err(|e| JsValue::from_str(&e))?;
+        dec.set_cancel_check(self.cancel_flag.clone().map(cancel_check));
+        if let Some(json) = generation_config_json {
+            let heads = alignment::heads_from_generation_config(&json)
+                .map_err(|e| JsValue::from_str(&e))?;
+            if let Some(heads) = heads {
+                dec.set_alignment_heads(heads).map_err(|e| JsValue::from_str(&e))?;
+            }
+        }
+        let report = LoadReport {
+            precision,
+            quantized: dec.is_quantized(),
+            model_bytes: model_len,
+            load_ms: js_sys::Date::now() - started,
+            peak_memory_bytes: memory_bytes(),
+        };
+        self.decoder = Some(dec);
+        serde_wasm_bindgen::to_value(&report).map_err(|e| JsValue::from_str(&format!("{e}")))
+    }
+
+    /// Shares an `Int32Array` over a `SharedArrayBuffer` whose first element
+    /// the main thread sets to non-zero to stop the running decode. Without
+    /// one, `cancel` can only drop work that has not started.
+    #[wasm_bindgen]
+    pub fn set_cancel_flag(&mut self, flag: js_sys::Int32Array) {
+        if let Some(decoder) = self.decoder.as_mut() {
+            decoder.set_cancel_check(Some(cancel_check(flag.clone())));
+        }
+        self.cancel_flag = Some(flag);
+    }
+
+    /// Acknowledges a cancellation by lowering the cancel flag, so later
+    /// decodes run.
+    #[wasm_bindgen]
+    pub fn cancel(&self) {
+        if let Some(flag) = &self.cancel_flag {
+            let _ = js_sys::Atomics::store(flag, 0, 0);
+        }
+    }
+
+    /// Pins the source language, or `"auto"` to detect it. When detecting,
+    /// a non-empty `allowed_languages` limits the choice to those codes.
+    #[wasm_bindgen]
+    pub fn set_language(
+        &mut self,
+        language: &str,
+        allowed_languages: Vec<String>,
     ) -> Result<(), JsValue> {
-        let dec = decoder::WhisperDecoder::new(model_bytes, tokenizer_json, config_json, mel_bytes)
+        self.options.language = decoder::LanguageSelection::from_code(language, &allowed_languages)
             .map_err(|e| JsValue::from_str(&e))?;
-        self.decoder = Some(dec);
         Ok(())
     }
 
+    /// Chooses between `"transcribe"` and `"translate"`, which makes Whisper
+    /// emit English text directly.
+    #[wasm_bindgen]
+    pub fn set_task(&mut self, task: &str) -> Result<(), JsValue> {
+        self.options.task = decoder::Task::from_id(task).map_err(|e| JsValue::from_str(&e))?;
+        Ok(())
+    }
+
+    /// Enables timed segments, placed on the stream's timeline.
+    #[wasm_bindgen]
+    pub fn set_timestamps(&mut self, enabled: bool) {
+        self.options.timestamps = enabled;
+    }
+
+    /// Times words by cross-attention alignment instead of spreading them
+    /// over their segment.
+    #[wasm_bindgen]
+    pub fn set_word_timestamps(&mut self, enabled: bool) {
+        self.options.word_timestamps = enabled;
+    }
+
+    /// Sets the decoding strategy and temperature fallback from a
+    /// `DecodingOptions` object.
+    #[wasm_bindgen]
+    pub fn set_decoding(&mut self, decoding: JsValue) -> Result<(), JsValue> {
+        self.options.decoding = serde_wasm_bindgen::from_value(decoding)
+            .map_err(|e| JsValue::from_str(&format!("Invalid decoding options: {e}")))?;
+        Ok(())
+    }
+
+    /// Sets the initial prompt and hotwords from a `PromptOptions` object.
+    #[wasm_bindgen]
+    pub fn set_prompt(&mut self, prompt: JsValue) -> Result<(), JsValue> {
+        self.options.prompt = serde_wasm_bindgen::from_value(prompt)
+            .map_err(|e| JsValue::from_str(&format!("Invalid prompt options: {e}")))?;
+        Ok(())
+    }
+
+    /// Configures token suppression, the no-speech check, repetition cutting
+    /// and the phrase blocklist from a `SafeguardOptions` object.
+    #[wasm_bindgen]
+    pub fn set_safeguards(&mut self, safeguards: JsValue) -> Result<(), JsValue> {
+        self.options.safeguards = serde_wasm_bindgen::from_value(safeguards)
+            .map_err(|e| JsValue::from_str(&format!("Invalid safeguard options: {e}")))?;
+        Ok(())
+    }
+
+    /// Configures the pre-processing chain (high-pass, noise reduction,
+    /// normalisation) from a `DspOptions` object. Restarts the chains'
+    /// filters.
+    #[wasm_bindgen]
+    pub fn set_dsp(&mut self, dsp: JsValue) -> Result<(), JsValue> {
+        let options = serde_wasm_bindgen::from_value(dsp)
+            .map_err(|e| JsValue::from_str(&format!("Invalid DSP options: {e}")))?;
+        self.streams.set_dsp(options);
+        Ok(())
+    }
+
+    /// Loads a speaker embedding model from safetensors bytes, used to
+    /// label segments with speakers once diarization is enabled.
     #[wasm_bindgen]
-    pub fn push_audio(&mut self, pcm: &[f32]) {
-        self.streaming.push(pcm);
+    pub fn load_speaker_model(&mut self, model_bytes: Vec<u8>) -> Result<(), JsValue> {
+        self.diarizer = None;
+        let encoder =
+            speaker::SpeakerEncoder::new(model_bytes).map_err(|e| JsValue::from_str(&e))?;
+        self.diarizer = Some(speaker::Diarizer::new(encoder));
+        Ok(())
     }
 
+    /// Configures speaker labelling from a `DiarizationOptions` object.
+    /// Speakers heard so far are forgotten when it is switched on.
     #[wasm_bindgen]
-    pub fn transcribe(&mut self) -> Result<JsValue, JsValue> {
+    pub fn set_diarization(&mut self, diarization: JsValue) -> Result<(), JsValue> {
+        let options: speaker::DiarizationOptions = serde_wasm_bindgen::from_value(diarization)
+            .map_err(|e| JsValue::from_str(&format!("Invalid diarization options: {e}")))?;
+        if options.enabled && !self.diarization.enabled {
+            if let Some(diarizer) = self.diarizer.as_mut() {
+                diarizer.reset();
+            }
+        }
+        self.diarization = options;
+        Ok(())
+    }
+
+    /// Buffers audio captured at `sample_rate` Hz, resampled to 16 kHz, and
+    /// returns the speech/silence changes it contains as `VadEvent`s.
+    /// `channel` (`"me"` or `"remote"`) keeps an input in its own buffer;
+    /// without one the audio goes to the shared stream.
+    #[wasm_bindgen]
+    pub fn push_audio(
+        &mut self,
+        pcm: &[f32],
+        sample_rate: u32,
+        channel: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        if sample_rate == 0 {
+            return Err(JsValue::from_str("Invalid sample rate: 0"));
+        }
+        let stream = self.streams.get_mut(parse_channel(channel.as_deref())?);
+        if sample_rate != stream.resampler.from_rate() {
+            stream.resampler = resample::Resampler::new(sample_rate, SAMPLE_RATE);
+        }
+        stream.dsp.count_clipping(pcm);
+        let pcm = stream.resampler.process(pcm);
+        let pcm = stream.dsp.process(&pcm);
+        let events = stream.streaming.push(&pcm);
+        serde_wasm_bindgen::to_value(&events).map_err(|e| JsValue::from_str(&format!("{e}")))
+    }
+
+    /// Forgets a stream's buffered audio, tentative words, cached mel frames
+    /// and filter state, so the next recording on `channel` starts at 0.
+    #[wasm_bindgen]
+    pub fn reset(&mut self, channel: Option<String>) -> Result<(), JsValue> {
+        self.streams.reset(parse_channel(channel.as_deref())?);
+        Ok(())
+    }
+
+    /// Decodes the buffered audio and returns a `StreamingUpdate`: text
+    /// that two consecutive hypotheses agree on is committed, the rest is
+    /// returned as a partial. Audio behind committed segments is trimmed.
+    /// A `channel` decodes that input's buffer and tags its segments.
+    /// Returns null when there was nothing new or the decode was cancelled.
+    #[wasm_bindgen]
+    pub fn transcribe(&mut self, channel: Option<String>) -> Result<JsValue, JsValue> {
+        self.decode_stream(channel, false)
+    }
+
+    /// Like `transcribe` once the input has stopped: decodes any speech not
+    /// yet decoded and commits all of it, tentative tail included. Returns
+    /// null when there is nothing left to commit.
+    #[wasm_bindgen]
+    pub fn finish(&mut self, channel: Option<String>) -> Result<JsValue, JsValue> {
+        self.decode_stream(channel, true)
+    }
+
+    fn decode_stream(&mut self, channel: Option<String>, finish: bool) -> Result<JsValue, JsValue> {
         let decoder = self
             .decoder
             .as_mut()
             .ok_or_else(|| JsValue::from_str("Model not loaded"))?;
+        let channel = parse_channel(channel.as_deref())?;
+        let stream = self.streams.get_mut(channel);
 
-        let audio = self.streaming.get_chunk();
+        let audio = if finish {
+            // Re-decode for a tentative tail, which has no audio of its own
+            let tentative = !stream.agreement.tentative().is_empty();
+            stream.streaming.get_final_chunk(tentative)
+        } else {
+            stream.streaming.get_chunk()
+        };
         if audio.is_empty() {
             return Ok(JsValue::NULL);
         }
 
-        let result = decoder
-            .transcribe(&audio)
-            .map_err(|e| JsValue::from_str(&e))?;
+        // Words are timed from segments, so always decode with timestamps
+        let options = decoder::TranscribeOptions {
+            timestamps: true,
+            ..self.options.clone()
+        };
+        let start_seconds = stream.streaming.start_seconds();
+        let prompt = stream.agreement.prompt(start_seconds);
+        let start_sample = stream.streaming.start_sample();
+        let mut result = match decoder.transcribe_stream(
+            &audio,
+            start_sample,
+            &mut stream.mel_cache,
+            &prompt,
+            &options,
+        ) {
+            Ok(result) => result,
+            Err(e) if e == decoder::CANCELLED => return Ok(JsValue::NULL),
+            Err(e) => return Err(JsValue::from_str(&e)),
+        };
+        result.offset_segments(start_seconds);
 
-        serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&format!("{e}")))
+        let mut committed = stream.agreement.insert(std::mem::take(&mut result.words));
+        // Speech paused, so the tail is as complete as it will get
+        if stream.streaming.ends_in_pause() {
+            committed.extend(stream.agreement.flush());
+        }
+
+        let diarizer = self.diarizer.as_mut().filter(|_| self.diarization.enabled);
+        // Tagged channels always get segments, to interleave by time
+        let mut segments: Vec<_> = if self.options.timestamps
+            || diarizer.is_some()
+            || channel.is_some()
+        {
+            let boundaries: Vec<f64> = result.segments.iter().map(|s| s.end).collect();
+            channels::tagged_segments(&committed, &boundaries, channel)
+        } else {
+            Vec::new()
+        };
+        if let Some(diarizer) = diarizer {
+            diarizer
+                .label_segments(&mut segments, &audio, start_seconds, &self.diarization)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+
+        if stream.streaming.duration_seconds() > TRIM_BUFFER_SECONDS {
+            let committed_end = stream.agreement.last_committed_end();
+            if let Some(end) = result
+                .segments
+                .iter()
+                .rev()
+                .map(|s| s.end)
+                .find(|&end| end <= committed_end + 1e-3)
+            {
+                stream.streaming.trim_before(end);
+            }
+        }
+
+        let update = agreement::StreamingUpdate {
+            text: agreement::words_to_text(&committed),
+            language: result.language,
+            language_probability: result.language_probability,
+            segments,
+            words: committed,
+            partial: agreement::words_to_text(stream.agreement.tentative()),
+            safeguards: result.safeguards,
+            clipping: stream.dsp.take_clipping(),
+            channel,
+        };
+        serde_wasm_bindgen::to_value(&update).map_err(|e| JsValue::from_str(&format!("{e}")))
     }
+
+    /// Transcribes a complete recording of any length into timed segments,
+    /// bypassing the streaming buffer but not the pre-processing chain.
+    /// Returns null if cancelled.
+    #[wasm_bindgen]
+    pub fn transcribe_long(&mut self, pcm: &[f32]) -> Result<JsValue, JsValue> {
+        let decoder = self
+            .decoder
+            .as_mut()
+            .ok_or_else(|| JsValue::from_str("Model not loaded"))?;
+
+        let pcm = audio::dsp::DspChain::process_all(self.streams.dsp_options().clone(), pcm);
+        let mut segments = match decoder.transcribe_long(&pcm, &self.options) {
+            Ok(segments) => segments,
+            Err(e) if e == decoder::CANCELLED => return Ok(JsValue::NULL),
+            Err(e) => return Err(JsValue::from_str(&e)),
+        };
+        if let Some(diarizer) = self.diarizer.as_mut().filter(|_| self.diarization.enabled) {
+            diarizer
+                .label_segments(&mut segments, &pcm, 0.0, &self.diarization)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+
+        serde_wasm_bindgen::to_value(&segments).map_err(|e| JsValue::from_str(&format!("{e}")))
+    }
+
+    /// Decodes an audio file (WAV, FLAC, Ogg/Vorbis or MP3) and transcribes
+    /// it as `transcribe_long` does.
+    #[wasm_bindgen]
+    pub fn transcribe_file(
+        &mut self,
+        bytes: Vec<u8>,
+        extension: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let pcm = audio_file::decode_audio_file(bytes, extension.as_deref())
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.transcribe_long(&pcm)
+    }
+}
+
+fn cancel_check(flag: js_sys::Int32Array) -> Box<dyn Fn() -> bool> {
+    Box::new(move || js_sys::Atomics::load(&flag, 0).is_ok_and(|raised| raised != 0))
+}
+
+fn parse_channel(channel: Option<&str>) -> Result<Option<channels::Channel>, JsValue> {
+    channel
+        .map(channels::Channel::from_id)
+        .transpose()
+        .map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn memory_bytes() -> Option<usize> {
+    // Pages of 64 KiB
+    Some(core::arch::wasm32::memory_size(0) * 65536)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn memory_bytes() -> Option<usize> {
+    None
 }
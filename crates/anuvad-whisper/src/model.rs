@@ -0,0 +1,698 @@
+//! Whisper encoder/decoder with incremental decoding.
+//!
+//! Loads the same weights as `candle_transformers::models::whisper::model`,
+//! but the text decoder keeps a self-attention KV cache per hypothesis and
+//! takes cross-attention keys/values computed once per audio window, so each
+//! decoding step only feeds the newest token.
+//!
+//! Weights come from safetensors or, like
+//! `candle_transformers::models::whisper::quantized_model`, from GGUF.
+
+use std::collections::HashMap;
+
+use candle_core::{DType, Device, Result, Shape, Tensor, D};
+use candle_nn::{Conv1d, Conv1dConfig, Embedding, LayerNorm, Module, VarBuilder};
+use candle_transformers::models::whisper::Config;
+use candle_transformers::quantized_nn;
+use candle_transformers::quantized_var_builder::VarBuilder as QVarBuilder;
+
+#[derive(serde::Deserialize)]
+struct SafetensorsEntry {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: (usize, usize),
+}
+
+// Bytes of a safetensors file released before its buffer is shrunk
+const SHRINK_STEP: usize = 256 << 20;
+
+/// Reads every tensor of a safetensors file, stored as `dtype`. Tensors are
+/// taken from the end of `bytes`, which is shrunk as they are read, so the
+/// file and the loaded weights are never both held in full.
+pub fn load_safetensors(
+    mut bytes: Vec<u8>,
+    dtype: DType,
+    device: &Device,
+) -> Result<HashMap<String, Tensor>> {
+    let header_len = bytes
+        .get(..8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| candle_core::Error::Msg("Safetensors file too short".into()))?;
+    let data_start = usize::try_from(header_len)
+        .ok()
+        .and_then(|len| len.checked_add(8))
+        .filter(|&start| start <= bytes.len())
+        .ok_or_else(|| candle_core::Error::Msg("Safetensors header out of bounds".into()))?;
+    let header: HashMap<String, serde_json::Value> = serde_json::from_slice(&bytes[8..data_start])
+        .map_err(|e| candle_core::Error::Msg(format!("Invalid safetensors header: {e}")))?;
+
+    let mut entries = header
+        .into_iter()
+        .filter(|(name, _)| name != "__metadata__")
+        .map(|(name, value)| {
+            serde_json::from_value::<SafetensorsEntry>(value)
+                .map(|entry| (name, entry))
+                .map_err(|e| candle_core::Error::Msg(format!("Invalid safetensors entry: {e}")))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.data_offsets.0));
+
+    let mut tensors = HashMap::with_capacity(entries.len());
+    for (name, entry) in entries {
+        let (start, end) = data_start
+            .checked_add(entry.data_offsets.0)
+            .zip(data_start.checked_add(entry.data_offsets.1))
+            .ok_or_else(|| candle_core::Error::Msg(format!("{name} is out of bounds")))?;
+        let data = bytes
+            .get(start..end)
+            .ok_or_else(|| candle_core::Error::Msg(format!("{name} is out of bounds")))?;
+        let file_dtype = entry
+            .dtype
+            .to_lowercase()
+            .parse::<DType>()
+            .map_err(|e| candle_core::Error::Msg(format!("{name}: {e}")))?;
+        let tensor = Tensor::from_raw_buffer(data, file_dtype, &entry.shape, device)?;
+        let tensor = if file_dtype.is_float() { tensor.to_dtype(dtype)? } else { tensor };
+        tensors.insert(name, tensor);
+        // Reallocating copies what is left, so only do it in large steps
+        bytes.truncate(start);
+        if bytes.capacity() - bytes.len() >= SHRINK_STEP {
+            bytes.shrink_to_fit();
+        }
+    }
+    Ok(tensors)
+}
+
+/// Where the weights are read from.
+#[derive(Clone)]
+pub enum Weights<'a> {
+    /// Safetensors tensors, as `load_safetensors` reads them. With an F16 or
+    /// BF16 builder, linear layers keep that precision and everything else
+    /// is converted to F32.
+    Full(VarBuilder<'a>),
+    /// GGUF: linear layers stay quantized, everything else is dequantized
+    /// on load.
+    Quantized(QVarBuilder),
+}
+
+impl Weights<'_> {
+    fn pp(&self, name: impl ToString) -> Self {
+        match self {
+            Self::Full(vb) => Self::Full(vb.pp(name)),
+            Self::Quantized(vb) => Self::Quantized(vb.pp(name)),
+        }
+    }
+
+    fn device(&self) -> &Device {
+        match self {
+            Self::Full(vb) => vb.device(),
+            Self::Quantized(vb) => vb.device(),
+        }
+    }
+
+    /// Loads a tensor in F32.
+    fn get<S: Into<Shape>>(&self, shape: S, name: &str) -> Result<Tensor> {
+        match self {
+            Self::Full(vb) => vb.get(shape, name)?.to_dtype(DType::F32),
+            Self::Quantized(vb) => vb.get(shape, name)?.dequantize(vb.device()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Linear {
+    Full(candle_nn::Linear),
+    /// Weight stored in F16 or BF16 and upcast for each product, trading
+    /// speed for memory.
+    Half {
+        weight: Tensor,
+        bias: Option<Tensor>,
+    },
+    Quantized(quantized_nn::Linear),
+}
+
+impl Module for Linear {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Full(linear) => linear.forward(x),
+            Self::Half { weight, bias } => {
+                candle_nn::Linear::new(weight.to_dtype(x.dtype())?, bias.clone()).forward(x)
+            }
+            Self::Quantized(linear) => linear.forward(x),
+        }
+    }
+}
+
+fn linear_b(in_dim: usize, out_dim: usize, bias: bool, vb: Weights) -> Result<Linear> {
+    match vb {
+        Weights::Full(ref full) if full.dtype() != DType::F32 => Ok(Linear::Half {
+            weight: full.get((out_dim, in_dim), "weight")?,
+            bias: if bias {
+                Some(vb.get(out_dim, "bias")?)
+            } else {
+                None
+            },
+        }),
+        Weights::Full(vb) => Ok(Linear::Full(candle_nn::linear_b(in_dim, out_dim, bias, vb)?)),
+        Weights::Quantized(vb) => Ok(Linear::Quantized(quantized_nn::linear_b(
+            in_dim, out_dim, bias, vb,
+        )?)),
+    }
+}
+
+fn linear(in_dim: usize, out_dim: usize, vb: Weights) -> Result<Linear> {
+    linear_b(in_dim, out_dim, true, vb)
+}
+
+fn linear_no_bias(in_dim: usize, out_dim: usize, vb: Weights) -> Result<Linear> {
+    linear_b(in_dim, out_dim, false, vb)
+}
+
+fn embedding(n_vocab: usize, n_state: usize, vb: Weights) -> Result<Embedding> {
+    Ok(Embedding::new(vb.get((n_vocab, n_state), "weight")?, n_state))
+}
+
+fn conv1d(
+    in_channels: usize,
+    out_channels: usize,
+    kernel_size: usize,
+    config: Conv1dConfig,
+    vb: Weights,
+) -> Result<Conv1d> {
+    let weight = vb.get((out_channels, in_channels, kernel_size), "weight")?;
+    let bias = vb.get(out_channels, "bias")?;
+    Ok(Conv1d::new(weight, Some(bias), config))
+}
+
+fn layer_norm(size: usize, vb: Weights) -> Result<LayerNorm> {
+    let weight = vb.get(size, "weight")?;
+    let bias = vb.get(size, "bias")?;
+    Ok(LayerNorm::new(weight, bias, 1e-5))
+}
+
+// https://github.com/openai/whisper/blob/f572f2161ba831bae131364c3bffdead7af6d210/whisper/model.py#L62
+#[derive(Debug, Clone)]
+struct MultiHeadAttention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    out: Linear,
+    n_head: usize,
+}
+
+impl MultiHeadAttention {
+    fn load(n_state: usize, n_head: usize, vb: Weights) -> Result<Self> {
+        Ok(Self {
+            query: linear(n_state, n_state, vb.pp("q_proj"))?,
+            key: linear_no_bias(n_state, n_state, vb.pp("k_proj"))?,
+            value: linear(n_state, n_state, vb.pp("v_proj"))?,
+            out: linear(n_state, n_state, vb.pp("out_proj"))?,
+            n_head,
+        })
+    }
+
+    fn key_value(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
+        Ok((self.key.forward(x)?, self.value.forward(x)?))
+    }
+
+    /// Self-attention over `x`, appending its keys/values to `cache`.
+    fn forward_self(
+        &self,
+        x: &Tensor,
+        mask: Option<&Tensor>,
+        cache: Option<&mut Option<(Tensor, Tensor)>>,
+    ) -> Result<Tensor> {
+        let q = self.query.forward(x)?;
+        let (mut k, mut v) = self.key_value(x)?;
+        if let Some(cache) = cache {
+            if let Some((prev_k, prev_v)) = cache.as_ref() {
+                k = Tensor::cat(&[prev_k, &k], 1)?;
+                v = Tensor::cat(&[prev_v, &v], 1)?;
+            }
+            *cache = Some((k.clone(), v.clone()));
+        }
+        let (wv, _) = self.qkv_attention(&q, &k, &v, mask)?;
+        self.out.forward(&wv)
+    }
+
+    /// Cross-attention against precomputed encoder keys/values. Also returns
+    /// the pre-softmax attention scores.
+    fn forward_cross(&self, x: &Tensor, kv: &(Tensor, Tensor)) -> Result<(Tensor, Tensor)> {
+        let q = self.query.forward(x)?;
+        let (wv, qk) = self.qkv_attention(&q, &kv.0, &kv.1, None)?;
+        Ok((self.out.forward(&wv)?, qk))
+    }
+
+    fn reshape_head(&self, x: &Tensor) -> Result<Tensor> {
+        let (n_batch, n_ctx, n_state) = x.dims3()?;
+        let target_dims = &[n_batch, n_ctx, self.n_head, n_state / self.n_head];
+        x.reshape(target_dims)?.transpose(1, 2)
+    }
+
+    fn qkv_attention(
+        &self,
+        q: &Tensor,
+        k: &Tensor,
+        v: &Tensor,
+        mask: Option<&Tensor>,
+    ) -> Result<(Tensor, Tensor)> {
+        let (_, _, n_state) = q.dims3()?;
+        let scale = ((n_state / self.n_head) as f64).powf(-0.25);
+        let q = (self.reshape_head(q)? * scale)?;
+        let k = (self.reshape_head(k)?.transpose(2, 3)? * scale)?;
+        let v = self.reshape_head(v)?.contiguous()?;
+        let mut qk = q.matmul(&k)?;
+        if let Some(mask) = mask {
+            qk = qk.broadcast_add(mask)?
+        }
+        let w = candle_nn::ops::softmax_last_dim(&qk)?;
+        let wv = w.matmul(&v)?.transpose(1, 2)?.flatten_from(2)?;
+        Ok((wv, qk))
+    }
+}
+
+// https://github.com/openai/whisper/blob/f572f2161ba831bae131364c3bffdead7af6d210/whisper/model.py#L111
+#[derive(Debug, Clone)]
+struct ResidualAttentionBlock {
+    attn: MultiHeadAttention,
+    attn_ln: LayerNorm,
+    cross_attn: Option<(MultiHeadAttention, LayerNorm)>,
+    mlp_linear1: Linear,
+    mlp_linear2: Linear,
+    mlp_ln: LayerNorm,
+}
+
+impl ResidualAttentionBlock {
+    fn load(n_state: usize, n_head: usize, ca: bool, vb: Weights) -> Result<Self> {
+        let attn = MultiHeadAttention::load(n_state, n_head, vb.pp("self_attn"))?;
+        let attn_ln = layer_norm(n_state, vb.pp("self_attn_layer_norm"))?;
+        let cross_attn = if ca {
+            let cross_attn = MultiHeadAttention::load(n_state, n_head, vb.pp("encoder_attn"))?;
+            let cross_attn_ln = layer_norm(n_state, vb.pp("encoder_attn_layer_norm"))?;
+            Some((cross_attn, cross_attn_ln))
+        } else {
+            None
+        };
+        let n_mlp = n_state * 4;
+        Ok(Self {
+            attn,
+            attn_ln,
+            cross_attn,
+            mlp_linear1: linear(n_state, n_mlp, vb.pp("fc1"))?,
+            mlp_linear2: linear(n_mlp, n_state, vb.pp("fc2"))?,
+            mlp_ln: layer_norm(n_state, vb.pp("final_layer_norm"))?,
+        })
+    }
+
+    fn forward(
+        &self,
+        x: &Tensor,
+        cross_kv: Option<&(Tensor, Tensor)>,
+        mask: Option<&Tensor>,
+        cache: Option<&mut Option<(Tensor, Tensor)>>,
+    ) -> Result<Tensor> {
+        Ok(self.forward_with_cross_qk(x, cross_kv, mask, cache)?.0)
+    }
+
+    /// Like `forward`, also returning the cross-attention scores when the
+    /// block has cross-attention.
+    fn forward_with_cross_qk(
+        &self,
+        x: &Tensor,
+        cross_kv: Option<&(Tensor, Tensor)>,
+        mask: Option<&Tensor>,
+        cache: Option<&mut Option<(Tensor, Tensor)>>,
+    ) -> Result<(Tensor, Option<Tensor>)> {
+        let attn = self
+            .attn
+            .forward_self(&self.attn_ln.forward(x)?, mask, cache)?;
+        let mut x = (x + attn)?;
+        let mut cross_qk = None;
+        if let (Some((attn, ln)), Some(kv)) = (&self.cross_attn, cross_kv) {
+            let (out, qk) = attn.forward_cross(&ln.forward(&x)?, kv)?;
+            x = (&x + out)?;
+            cross_qk = Some(qk);
+        }
+        let mlp = self.mlp_linear2.forward(
+            &self
+                .mlp_linear1
+                .forward(&self.mlp_ln.forward(&x)?)?
+                .gelu()?,
+        )?;
+        Ok(((x + mlp)?, cross_qk))
+    }
+}
+
+fn sinusoids(length: usize, channels: usize, device: &Device) -> Result<Tensor> {
+    let max_timescale = 10000f32;
+    let log_timescale_increment = max_timescale.ln() / (channels / 2 - 1) as f32;
+    let inv_timescales: Vec<_> = (0..channels / 2)
+        .map(|i| (i as f32 * (-log_timescale_increment)).exp())
+        .collect();
+    let inv_timescales = Tensor::new(inv_timescales.as_slice(), device)?.unsqueeze(0)?;
+    let arange = Tensor::arange(0, length as u32, device)?
+        .to_dtype(candle_core::DType::F32)?
+        .unsqueeze(1)?;
+    let sh = (length, channels / 2);
+    let scaled_time = (arange.broadcast_as(sh)? * inv_timescales.broadcast_as(sh)?)?;
+    Tensor::cat(&[scaled_time.sin()?, scaled_time.cos()?], 1)
+}
+
+// https://github.com/openai/whisper/blob/f572f2161ba831bae131364c3bffdead7af6d210/whisper/model.py#L143
+#[derive(Debug, Clone)]
+pub struct AudioEncoder {
+    conv1: Conv1d,
+    conv2: Conv1d,
+    positional_embedding: Tensor,
+    blocks: Vec<ResidualAttentionBlock>,
+    ln_post: LayerNorm,
+}
+
+impl AudioEncoder {
+    fn load(vb: Weights, cfg: &Config) -> Result<Self> {
+        let n_state = cfg.d_model;
+        let n_head = cfg.encoder_attention_heads;
+        let cfg1 = Conv1dConfig {
+            padding: 1,
+            stride: 1,
+            groups: 1,
+            dilation: 1,
+            cudnn_fwd_algo: None,
+        };
+        let cfg2 = Conv1dConfig { stride: 2, ..cfg1 };
+        let blocks = (0..cfg.encoder_layers)
+            .map(|i| {
+                ResidualAttentionBlock::load(n_state, n_head, false, vb.pp(format!("layers.{i}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            conv1: conv1d(cfg.num_mel_bins, n_state, 3, cfg1, vb.pp("conv1"))?,
+            conv2: conv1d(n_state, n_state, 3, cfg2, vb.pp("conv2"))?,
+            positional_embedding: sinusoids(cfg.max_source_positions, n_state, vb.device())?,
+            blocks,
+            ln_post: layer_norm(n_state, vb.pp("layer_norm"))?,
+        })
+    }
+
+    pub fn forward(&self, mel: &Tensor) -> Result<Tensor> {
+        let x = self.conv1.forward(mel)?.gelu()?;
+        let x = self.conv2.forward(&x)?.gelu()?;
+        let x = x.transpose(1, 2)?;
+        let (_, seq_len, _) = x.dims3()?;
+        let positional_embedding = self.positional_embedding.narrow(0, 0, seq_len)?;
+        let mut x = x.broadcast_add(&positional_embedding)?;
+        for block in self.blocks.iter() {
+            x = block.forward(&x, None, None, None)?
+        }
+        self.ln_post.forward(&x)
+    }
+}
+
+/// Encoder keys/values for every decoder layer's cross-attention, computed
+/// once per audio window and shared by all hypotheses.
+#[derive(Debug, Clone)]
+pub struct CrossKvCache {
+    layers: Vec<(Tensor, Tensor)>,
+}
+
+/// Self-attention keys/values for the tokens a hypothesis has fed so far.
+/// Cloning is cheap, so beams can branch off a shared prefix.
+#[derive(Debug, Clone)]
+pub struct KvCache {
+    layers: Vec<Option<(Tensor, Tensor)>>,
+    len: usize,
+}
+
+impl KvCache {
+    /// Number of tokens already fed through the decoder.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// https://github.com/openai/whisper/blob/f572f2161ba831bae131364c3bffdead7af6d210/whisper/model.py#L176
+// (layer, head) pairs to keep cross-attention scores for, and a slot for each
+type HeadScores<'a> = (&'a [(usize, usize)], &'a mut [Option<Tensor>]);
+
+#[derive(Debug, Clone)]
+pub struct TextDecoder {
+    token_embedding: Embedding,
+    positional_embedding: Tensor,
+    blocks: Vec<ResidualAttentionBlock>,
+    ln: LayerNorm,
+}
+
+impl TextDecoder {
+    fn load(vb: Weights, cfg: &Config) -> Result<Self> {
+        let n_state = cfg.d_model;
+        let n_head = cfg.decoder_attention_heads;
+        let blocks = (0..cfg.decoder_layers)
+            .map(|i| {
+                ResidualAttentionBlock::load(n_state, n_head, true, vb.pp(format!("layers.{i}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            token_embedding: embedding(cfg.vocab_size, n_state, vb.pp("embed_tokens"))?,
+            positional_embedding: vb
+                .get((cfg.max_target_positions, n_state), "embed_positions.weight")?,
+            blocks,
+            ln: layer_norm(n_state, vb.pp("layer_norm"))?,
+        })
+    }
+
+    pub fn cross_kv(&self, encoder_output: &Tensor) -> Result<CrossKvCache> {
+        let layers = self
+            .blocks
+            .iter()
+            .map(|block| match &block.cross_attn {
+                Some((attn, _)) => attn.key_value(encoder_output),
+                None => candle_core::bail!("decoder block without cross-attention"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CrossKvCache { layers })
+    }
+
+    pub fn empty_cache(&self) -> KvCache {
+        KvCache {
+            layers: vec![None; self.blocks.len()],
+            len: 0,
+        }
+    }
+
+    /// Feeds `tokens` (batch, seq) after the ones already in `cache` and
+    /// returns their hidden states.
+    pub fn forward(
+        &self,
+        tokens: &Tensor,
+        cross_kv: &CrossKvCache,
+        cache: &mut KvCache,
+    ) -> Result<Tensor> {
+        self.forward_blocks(tokens, cross_kv, cache, None)
+    }
+
+    /// Feeds `tokens` (batch, seq) from scratch and returns the pre-softmax
+    /// cross-attention scores of each of `heads`, (layer, head) pairs, as
+    /// (seq, audio frame) for the first batch item. Other heads' scores are
+    /// dropped layer by layer.
+    pub fn cross_attention_qk(
+        &self,
+        tokens: &Tensor,
+        cross_kv: &CrossKvCache,
+        heads: &[(usize, usize)],
+    ) -> Result<Vec<Tensor>> {
+        let mut cross_qk = vec![None; heads.len()];
+        let mut cache = self.empty_cache();
+        self.forward_blocks(tokens, cross_kv, &mut cache, Some((heads, &mut cross_qk)))?;
+        cross_qk
+            .into_iter()
+            .zip(heads)
+            .map(|(qk, (layer, head))| {
+                qk.ok_or_else(|| {
+                    candle_core::Error::Msg(format!("No layer {layer} for head {head}"))
+                })
+            })
+            .collect()
+    }
+
+    fn forward_blocks(
+        &self,
+        tokens: &Tensor,
+        cross_kv: &CrossKvCache,
+        cache: &mut KvCache,
+        mut cross_qk: Option<HeadScores>,
+    ) -> Result<Tensor> {
+        let seq_len = tokens.dim(D::Minus1)?;
+        let offset = cache.len;
+        let token_embedding = self.token_embedding.forward(tokens)?;
+        let positional_embedding = self.positional_embedding.narrow(0, offset, seq_len)?;
+        let mut x = token_embedding.broadcast_add(&positional_embedding)?;
+
+        // New tokens see the whole cached prefix plus themselves
+        let mask = if seq_len > 1 {
+            let mask: Vec<f32> = (0..seq_len)
+                .flat_map(|i| {
+                    (0..offset + seq_len).map(move |j| {
+                        if j > offset + i {
+                            f32::NEG_INFINITY
+                        } else {
+                            0.0
+                        }
+                    })
+                })
+                .collect();
+            Some(Tensor::from_vec(mask, (seq_len, offset + seq_len), tokens.device())?)
+        } else {
+            None
+        };
+
+        for (layer, ((block, layer_cache), kv)) in self
+            .blocks
+            .iter()
+            .zip(cache.layers.iter_mut())
+            .zip(cross_kv.layers.iter())
+            .enumerate()
+        {
+            let (out, qk) =
+                block.forward_with_cross_qk(&x, Some(kv), mask.as_ref(), Some(layer_cache))?;
+            x = out;
+            if let (Some((heads, cross_qk)), Some(qk)) = (cross_qk.as_mut(), qk) {
+                for (&(l, head), slot) in heads.iter().zip(cross_qk.iter_mut()) {
+                    if l == layer {
+                        // A copy, so the layer's other heads can be freed
+                        *slot = Some(qk.get(0)?.get(head)?.copy()?);
+                    }
+                }
+            }
+        }
+        cache.len += seq_len;
+        self.ln.forward(&x)
+    }
+
+    pub fn final_linear(&self, x: &Tensor) -> Result<Tensor> {
+        let b_size = x.dim(0)?;
+        let w = self.token_embedding.embeddings().broadcast_left(b_size)?;
+        x.matmul(&w.t()?)
+    }
+}
+
+// https://github.com/openai/whisper/blob/f572f2161ba831bae131364c3bffdead7af6d210/whisper/model.py#L221
+#[derive(Debug, Clone)]
+pub struct Whisper {
+    pub encoder: AudioEncoder,
+    pub decoder: TextDecoder,
+    pub config: Config,
+}
+
+impl Whisper {
+    pub fn load(vb: &Weights, config: Config) -> Result<Self> {
+        let encoder = AudioEncoder::load(vb.pp("model.encoder"), &config)?;
+        let decoder = TextDecoder::load(vb.pp("model.decoder"), &config)?;
+        Ok(Self {
+            encoder,
+            decoder,
+            config,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_safetensors_reads_every_tensor_at_the_requested_dtype() {
+        let device = Device::Cpu;
+        let weight = Tensor::new(&[[1.0f32, -2.0], [0.5, 4.0]], &device).unwrap();
+        let ids = Tensor::new(&[3u32, 7, 9], &device).unwrap();
+        let tensors = HashMap::from([("weight".to_string(), weight), ("ids".to_string(), ids)]);
+        let path = std::env::temp_dir().join("anuvad_load_safetensors_test.safetensors");
+        candle_core::safetensors::save(&tensors, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = load_safetensors(bytes, DType::F16, &device).unwrap();
+        assert_eq!(loaded.len(), 2);
+        let weight = &loaded["weight"];
+        assert_eq!(weight.dtype(), DType::F16);
+        assert_eq!(
+            weight.to_dtype(DType::F32).unwrap().to_vec2::<f32>().unwrap(),
+            vec![vec![1.0, -2.0], vec![0.5, 4.0]]
+        );
+        // Integer tensors keep their type
+        assert_eq!(loaded["ids"].to_vec1::<u32>().unwrap(), vec![3, 7, 9]);
+    }
+
+    #[test]
+    fn load_safetensors_rejects_a_truncated_file() {
+        assert!(load_safetensors(vec![1, 2, 3], DType::F32, &Device::Cpu).is_err());
+        let mut bytes = 100u64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"{}");
+        assert!(load_safetensors(bytes, DType::F32, &Device::Cpu).is_err());
+
+        // A header length that overflows, and offsets past the data
+        let bytes = u64::MAX.to_le_bytes().to_vec();
+        assert!(load_safetensors(bytes, DType::F32, &Device::Cpu).is_err());
+        let header = format!(
+            r#"{{"w": {{"dtype": "F32", "shape": [1], "data_offsets": [0, {}]}}}}"#,
+            usize::MAX
+        );
+        let header = header.as_bytes();
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header);
+        bytes.extend_from_slice(&[0; 4]);
+        assert!(load_safetensors(bytes, DType::F32, &Device::Cpu).is_err());
+    }
+
+    #[test]
+    fn cached_decoding_matches_a_full_forward() {
+        let device = Device::Cpu;
+        let config = Config {
+            num_mel_bins: 8,
+            max_source_positions: 6,
+            d_model: 16,
+            encoder_attention_heads: 2,
+            encoder_layers: 1,
+            vocab_size: 24,
+            max_target_positions: 10,
+            decoder_attention_heads: 2,
+            decoder_layers: 2,
+            suppress_tokens: vec![],
+        };
+        // Create the variables, then give them random values
+        let varmap = candle_nn::VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        TextDecoder::load(Weights::Full(vb.clone()), &config).unwrap();
+        for var in varmap.data().lock().unwrap().values() {
+            let random = Tensor::randn(0f32, 0.5, var.shape(), &device).unwrap();
+            var.set(&random).unwrap();
+        }
+        let decoder = TextDecoder::load(Weights::Full(vb), &config).unwrap();
+        let audio = Tensor::randn(0f32, 1.0, (1, 6, 16), &device).unwrap();
+        let cross_kv = decoder.cross_kv(&audio).unwrap();
+
+        let tokens = [3u32, 17, 5, 9, 0, 21];
+        let input = Tensor::new(&tokens[..], &device).unwrap().unsqueeze(0).unwrap();
+        let hidden = decoder.forward(&input, &cross_kv, &mut decoder.empty_cache()).unwrap();
+        let full = decoder.final_linear(&hidden).unwrap().squeeze(0).unwrap();
+
+        // A two-token prefix, then one token at a time
+        let mut cache = decoder.empty_cache();
+        let mut steps = Vec::new();
+        for chunk in [&tokens[..2], &tokens[2..3], &tokens[3..4], &tokens[4..5], &tokens[5..]] {
+            let input = Tensor::new(chunk, &device).unwrap().unsqueeze(0).unwrap();
+            let hidden = decoder.forward(&input, &cross_kv, &mut cache).unwrap();
+            steps.push(decoder.final_linear(&hidden).unwrap().squeeze(0).unwrap());
+        }
+        assert_eq!(cache.len(), tokens.len());
+        let cached = Tensor::cat(&steps, 0).unwrap();
+
+        let peak = full.abs().unwrap().max_all().unwrap();
+        assert!(peak.to_scalar::<f32>().unwrap() > 0.1);
+        let diff = (full - cached).unwrap().abs().unwrap().max_all().unwrap();
+        assert!(diff.to_scalar::<f32>().unwrap() < 1e-4);
+    }
+}
@@ -0,0 +1,240 @@
+use std::f64::consts::PI;
+
+// Zero crossings of the sinc kernel on each side of a sample; more gives a
+// sharper cutoff at the cost of speed
+const ZERO_CROSSINGS: usize = 16;
+// Cutoff as a fraction of the lower Nyquist frequency, leaving room for the
+// filter's transition band
+const ROLLOFF: f64 = 0.945;
+// Fractional positions the kernel is tabulated at between input samples;
+// others are interpolated
+const PHASES: usize = 512;
+
+/// Converts `samples` from `from_rate` to `to_rate` Hz in one go; see
+/// `Resampler`.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    let mut resampler = Resampler::new(from_rate, to_rate);
+    let mut out = resampler.process(samples);
+    out.extend(resampler.flush());
+    out
+}
+
+/// Streaming sample rate converter using a Blackman-windowed sinc filter.
+/// The cutoff sits just below the lower of the two Nyquist frequencies, so
+/// downsampling removes content the output cannot represent instead of
+/// aliasing it. Chunks can be any length; output lags the input by half the
+/// kernel, about a millisecond.
+///
+/// The kernel is tabulated once, as `PHASES + 1` rows of `taps` weights:
+/// row `p` holds the weights for a first tap `p / PHASES` of an input
+/// sample past the kernel's left edge.
+pub struct Resampler {
+    from_rate: u32,
+    /// Input samples per output sample.
+    step: f64,
+    /// Kernel half-width in input samples.
+    half_width: f64,
+    taps: usize,
+    table: Vec<f32>,
+    /// Input from sample `offset` of the stream on, kept for the kernel.
+    input: Vec<f32>,
+    offset: usize,
+    next_output: u64,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let ratio = to_rate as f64 / from_rate as f64;
+        // In cycles per input sample, relative to the input Nyquist frequency
+        let cutoff = ratio.min(1.0) * ROLLOFF;
+        let half_width = ZERO_CROSSINGS as f64 / cutoff;
+        let taps = (2.0 * half_width).ceil() as usize + 1;
+        let table = (0..=PHASES)
+            .flat_map(|p| {
+                let first = p as f64 / PHASES as f64 - half_width;
+                (0..taps).map(move |j| kernel(first + j as f64, cutoff, half_width) as f32)
+            })
+            .collect();
+        Self {
+            from_rate,
+            step: 1.0 / ratio,
+            half_width,
+            taps,
+            table,
+            input: Vec::new(),
+            offset: 0,
+            next_output: 0,
+        }
+    }
+
+    pub fn from_rate(&self) -> u32 {
+        self.from_rate
+    }
+
+    fn passthrough(&self) -> bool {
+        self.step == 1.0
+    }
+
+    /// Feeds the next chunk of input and returns the output samples whose
+    /// kernel it completes.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        if self.passthrough() {
+            return samples.to_vec();
+        }
+        self.input.extend_from_slice(samples);
+        let end = (self.offset + self.input.len()) as f64;
+
+        let mut out = Vec::new();
+        loop {
+            let center = self.center();
+            if center + self.half_width >= end {
+                break;
+            }
+            out.push(self.output_at(center));
+            self.next_output += 1;
+        }
+
+        // Drop input that no later output reaches
+        let needed = ((self.center() - self.half_width).ceil().max(0.0) as usize).max(self.offset);
+        let stale = (needed - self.offset).min(self.input.len());
+        self.input.drain(..stale);
+        self.offset += stale;
+        out
+    }
+
+    /// Returns the outputs still waiting on input, as if the stream ended
+    /// with silence.
+    pub fn flush(&mut self) -> Vec<f32> {
+        if self.passthrough() {
+            return Vec::new();
+        }
+        let end = (self.offset + self.input.len()) as f64;
+        let mut out = Vec::new();
+        while self.center() < end {
+            out.push(self.output_at(self.center()));
+            self.next_output += 1;
+        }
+        self.input.clear();
+        out
+    }
+
+    // Position of the next output sample, in input samples
+    fn center(&self) -> f64 {
+        self.next_output as f64 * self.step
+    }
+
+    fn output_at(&self, center: f64) -> f32 {
+        let edge = center - self.half_width;
+        let first = edge.ceil();
+        let position = (first - edge) * PHASES as f64;
+        let phase = (position as usize).min(PHASES - 1);
+        let t = (position - phase as f64) as f32;
+        let row = &self.table[phase * self.taps..(phase + 1) * self.taps];
+        let next = &self.table[(phase + 1) * self.taps..(phase + 2) * self.taps];
+
+        // Taps before the kept input or past its end read silence
+        let first = first as i64;
+        let skip = (self.offset as i64 - first).max(0) as usize;
+        let start = ((first + skip as i64) as usize - self.offset).min(self.input.len());
+        let count = self.taps.saturating_sub(skip).min(self.input.len().saturating_sub(start));
+        self.input[start..start + count]
+            .iter()
+            .zip(&row[skip..])
+            .zip(&next[skip..])
+            .map(|((&x, &a), &b)| x * (a + (b - a) * t))
+            .sum()
+    }
+}
+
+fn kernel(x: f64, cutoff: f64, half_width: f64) -> f64 {
+    if x.abs() >= half_width {
+        return 0.0;
+    }
+    let sinc = if x == 0.0 {
+        1.0
+    } else {
+        let a = PI * cutoff * x;
+        a.sin() / a
+    };
+    let phase = PI * x / half_width;
+    let window = 0.42 + 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+    cutoff * sinc * window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (0.5 * (2.0 * PI * frequency * i as f64 / rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    // The middle half, away from the kernel's edge effects
+    fn middle(pcm: &[f32]) -> &[f32] {
+        &pcm[pcm.len() / 4..pcm.len() * 3 / 4]
+    }
+
+    #[test]
+    fn output_length_follows_the_ratio() {
+        assert_eq!(resample(&vec![0.0; 48000], 48000, 16000).len(), 16000);
+        assert_eq!(resample(&vec![0.0; 44100], 44100, 16000).len(), 16000);
+        assert_eq!(resample(&vec![0.0; 8000], 8000, 16000).len(), 16000);
+        assert_eq!(resample(&[0.0; 10], 48000, 16000).len(), 4);
+    }
+
+    #[test]
+    fn keeps_tones_below_the_cutoff() {
+        for from_rate in [8000, 44100, 48000] {
+            let out = resample(&sine(1000.0, from_rate, from_rate as usize), from_rate, 16000);
+            let expected = sine(1000.0, 16000, out.len());
+            let error = middle(&out)
+                .iter()
+                .zip(middle(&expected))
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max);
+            assert!(error < 2e-3, "{from_rate} Hz: error {error}");
+        }
+    }
+
+    #[test]
+    fn removes_tones_above_the_output_nyquist() {
+        let out = resample(&sine(10000.0, 48000, 48000), 48000, 16000);
+        let peak = middle(&out).iter().map(|x| x.abs()).fold(0.0, f32::max);
+        assert!(peak < 1e-3, "aliased peak {peak}");
+    }
+
+    #[test]
+    fn table_matches_the_kernel() {
+        let input = sine(3000.0, 44100, 400);
+        let mut resampler = Resampler::new(44100, 16000);
+        resampler.input = input.clone();
+        let cutoff = 16000.0 / 44100.0 * ROLLOFF;
+        for center in [60.0, 123.456, 200.999, 301.5] {
+            let direct: f64 = (0..input.len())
+                .map(|j| input[j] as f64 * kernel(j as f64 - center, cutoff, resampler.half_width))
+                .sum();
+            let tabulated = resampler.output_at(center) as f64;
+            assert!((tabulated - direct).abs() < 1e-4, "{center}: {tabulated} vs {direct}");
+        }
+    }
+
+    #[test]
+    fn chunking_does_not_change_output() {
+        let input = sine(440.0, 44100, 20000);
+        let whole = resample(&input, 44100, 16000);
+        let mut resampler = Resampler::new(44100, 16000);
+        let mut chunked: Vec<f32> = input.chunks(333).flat_map(|c| resampler.process(c)).collect();
+        chunked.extend(resampler.flush());
+        assert_eq!(chunked, whole);
+    }
+
+    #[test]
+    fn equal_rates_pass_through() {
+        let input = sine(440.0, 16000, 100);
+        let mut resampler = Resampler::new(16000, 16000);
+        assert_eq!(resampler.process(&input), input);
+        assert!(resampler.flush().is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::timestamps::Segment;
+
+// Phrases Whisper is known to invent over silence or music, mostly from
+// subtitle credits in its training data
+const DEFAULT_BLOCKLIST: &[&str] = &[
+    "thank you for watching",
+    "thanks for watching",
+    "please subscribe",
+    "like and subscribe",
+    "subtitles by the amara org community",
+    "untertitel der amara org community",
+    "ご視聴ありがとうございました",
+    "字幕由amara org社区提供",
+];
+
+/// Guards against Whisper's usual failure modes: blank or special tokens,
+/// text over silence, repetition loops and stock phrases.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SafeguardOptions {
+    /// Mask the model's non-speech symbols and control tokens.
+    pub suppress_tokens: bool,
+    /// Keep the first sampled token from being a space or end-of-text.
+    pub suppress_blank: bool,
+    /// Drop the output when `<|nospeech|>` is more likely than this and the
+    /// pass's average log-probability is below the fallback threshold.
+    pub no_speech_threshold: Option<f32>,
+    /// Cut at the first n-gram of up to `max_ngram` tokens repeated
+    /// `min_repeats` times in a row, keeping one copy.
+    pub max_ngram: usize,
+    pub min_repeats: usize,
+    /// Segments whose whole text is one of these phrases are dropped.
+    /// Matching ignores case and punctuation.
+    pub blocklist: Vec<String>,
+}
+
+impl Default for SafeguardOptions {
+    fn default() -> Self {
+        Self {
+            suppress_tokens: true,
+            suppress_blank: true,
+            no_speech_threshold: Some(0.6),
+            max_ngram: 8,
+            min_repeats: 4,
+            blocklist: DEFAULT_BLOCKLIST.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+/// A safeguard that changed the output.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Safeguard {
+    /// Everything was dropped as likely silence.
+    NoSpeech { probability: f32 },
+    /// A repetition loop was cut, removing this text.
+    RepetitionCut { removed: String },
+    /// This text matched the blocklist and was dropped.
+    Blocklisted { text: String },
+}
+
+impl SafeguardOptions {
+    /// Whether the output should be dropped as silence.
+    pub fn is_no_speech(
+        &self,
+        no_speech_probability: f32,
+        avg_logprob: f64,
+        logprob_threshold: Option<f64>,
+    ) -> bool {
+        let quiet = self
+            .no_speech_threshold
+            .is_some_and(|t| no_speech_probability > t);
+        // Confident text overrides the no-speech signal
+        let confident = logprob_threshold.is_some_and(|t| avg_logprob > t);
+        quiet && !confident
+    }
+
+    /// Drops blocklisted segments and returns the safeguards applied.
+    pub fn filter_segments(&self, segments: &mut Vec<Segment>) -> Vec<Safeguard> {
+        let mut applied = Vec::new();
+        segments.retain(|segment| {
+            let blocked = self.is_blocked(&segment.text);
+            if blocked {
+                applied.push(Safeguard::Blocklisted {
+                    text: segment.text.clone(),
+                });
+            }
+            !blocked
+        });
+        applied
+    }
+
+    pub fn is_blocked(&self, text: &str) -> bool {
+        let text = normalize(text);
+        !text.is_empty() && self.blocklist.iter().any(|p| normalize(p) == text)
+    }
+}
+
+/// Index just past the first copy of the first n-gram repeated
+/// `min_repeats` times in a row, or `None` when there is no loop.
+pub fn find_loop(tokens: &[u32], max_ngram: usize, min_repeats: usize) -> Option<usize> {
+    if min_repeats < 2 {
+        return None;
+    }
+    for start in 0..tokens.len() {
+        for n in 1..=max_ngram {
+            if start + n * min_repeats > tokens.len() {
+                break;
+            }
+            let gram = &tokens[start..start + n];
+            if (1..min_repeats).all(|r| &tokens[start + r * n..start + (r + 1) * n] == gram) {
+                return Some(start + n);
+            }
+        }
+    }
+    None
+}
+
+/// Whether `tokens` end in an n-gram repeated `min_repeats` times, so
+/// decoding can stop early.
+pub fn ends_in_loop(tokens: &[u32], max_ngram: usize, min_repeats: usize) -> bool {
+    min_repeats >= 2
+        && (1..=max_ngram).any(|n| {
+            let len = n * min_repeats;
+            len <= tokens.len() && {
+                let tail = &tokens[tokens.len() - len..];
+                let gram = &tail[..n];
+                tail.chunks_exact(n).all(|c| c == gram)
+            }
+        })
+}
+
+// Lowercase words without punctuation, single-spaced
+fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_first_repeated_ngram() {
+        assert_eq!(find_loop(&[1, 2, 3, 3, 3, 3, 4], 8, 4), Some(3));
+        // "5 6" four times after a prefix; one copy is kept
+        assert_eq!(find_loop(&[1, 2, 5, 6, 5, 6, 5, 6, 5, 6], 8, 4), Some(4));
+        assert_eq!(find_loop(&[7, 8, 9, 7, 8, 9, 7, 8, 9, 7, 8, 9], 2, 4), None);
+        assert_eq!(find_loop(&[1, 1, 1, 2], 8, 4), None);
+        assert_eq!(find_loop(&[1, 1, 1, 1], 8, 1), None);
+        assert_eq!(find_loop(&[], 8, 4), None);
+    }
+
+    #[test]
+    fn detects_loops_at_the_end() {
+        assert!(ends_in_loop(&[9, 1, 2, 1, 2, 1, 2, 1, 2], 8, 4));
+        assert!(!ends_in_loop(&[1, 2, 1, 2, 1, 2, 1, 2, 9], 8, 4));
+        assert!(!ends_in_loop(&[1, 1, 1], 8, 4));
+    }
+
+    #[test]
+    fn confident_text_overrides_no_speech() {
+        let options = SafeguardOptions::default();
+        assert!(options.is_no_speech(0.9, -1.5, Some(-1.0)));
+        assert!(!options.is_no_speech(0.9, -0.5, Some(-1.0)));
+        assert!(!options.is_no_speech(0.3, -1.5, Some(-1.0)));
+        assert!(options.is_no_speech(0.9, -0.5, None));
+        let off = SafeguardOptions {
+            no_speech_threshold: None,
+            ..options
+        };
+        assert!(!off.is_no_speech(1.0, -5.0, Some(-1.0)));
+    }
+
+    #[test]
+    fn drops_blocklisted_segments_only() {
+        let options = SafeguardOptions::default();
+        assert!(options.is_blocked(" Thanks for watching!"));
+        assert!(!options.is_blocked("Thanks for watching the whole thing"));
+        assert!(!options.is_blocked(" ..."));
+
+        let segment = |text: &str| Segment::new(0.0, 1.0, text.into(), vec![], vec![]);
+        let mut segments = vec![segment(" Hello."), segment(" Please subscribe.")];
+        let applied = options.filter_segments(&mut segments);
+        assert_eq!(segments, [segment(" Hello.")]);
+        assert_eq!(
+            applied,
+            [Safeguard::Blocklisted {
+                text: " Please subscribe.".into()
+            }]
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use candle_core::{DType, Device, Module, Tensor, D};
+use candle_nn::{BatchNorm, Conv2d, Conv2dConfig, Linear, ModuleT, VarBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::fbank::Fbank;
+
+const SAMPLE_RATE: usize = 16000;
+const N_MELS: usize = 80;
+const BN_EPS: f64 = 1e-5;
+// Added to the variance in statistics pooling, as in WeSpeaker
+const VAR_EPS: f64 = 1e-7;
+// Shorter segments are too little voice to tell speakers apart and keep the
+// previous speaker
+const MIN_SEGMENT_SECONDS: f64 = 1.0;
+// Longer segments are embedded from their middle to bound the cost
+const MAX_SEGMENT_SECONDS: f64 = 10.0;
+
+/// How transcript segments are attributed to speakers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DiarizationOptions {
+    pub enabled: bool,
+    /// Cosine similarity to a known speaker below which a new one is started.
+    pub threshold: f32,
+    /// Once reached, segments go to the closest known speaker.
+    pub max_speakers: usize,
+}
+
+impl Default for DiarizationOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 0.5,
+            max_speakers: 8,
+        }
+    }
+}
+
+struct BasicBlock {
+    conv1: Conv2d,
+    bn1: BatchNorm,
+    conv2: Conv2d,
+    bn2: BatchNorm,
+    shortcut: Option<(Conv2d, BatchNorm)>,
+}
+
+fn conv(
+    in_channels: usize,
+    out_channels: usize,
+    kernel: usize,
+    stride: usize,
+    vb: VarBuilder,
+) -> candle_core::Result<Conv2d> {
+    let config = Conv2dConfig {
+        padding: kernel / 2,
+        stride,
+        ..Default::default()
+    };
+    candle_nn::conv2d_no_bias(in_channels, out_channels, kernel, config, vb)
+}
+
+impl BasicBlock {
+    fn load(
+        in_channels: usize,
+        channels: usize,
+        stride: usize,
+        vb: VarBuilder,
+    ) -> candle_core::Result<Self> {
+        let shortcut = if stride != 1 || in_channels != channels {
+            Some((
+                conv(in_channels, channels, 1, stride, vb.pp("shortcut.0"))?,
+                candle_nn::batch_norm(channels, BN_EPS, vb.pp("shortcut.1"))?,
+            ))
+        } else {
+            None
+        };
+        Ok(Self {
+            conv1: conv(in_channels, channels, 3, stride, vb.pp("conv1"))?,
+            bn1: candle_nn::batch_norm(channels, BN_EPS, vb.pp("bn1"))?,
+            conv2: conv(channels, channels, 3, 1, vb.pp("conv2"))?,
+            bn2: candle_nn::batch_norm(channels, BN_EPS, vb.pp("bn2"))?,
+            shortcut,
+        })
+    }
+
+    fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+        let out = self.bn1.forward_t(&self.conv1.forward(x)?, false)?.relu()?;
+        let out = self.bn2.forward_t(&self.conv2.forward(&out)?, false)?;
+        let residual = match &self.shortcut {
+            Some((conv, bn)) => bn.forward_t(&conv.forward(x)?, false)?,
+            None => x.clone(),
+        };
+        (out + residual)?.relu()
+    }
+}
+
+/// A WeSpeaker-style ResNet speaker embedding model (as exported for
+/// pyannote and WeSpeaker's ResNet34): four stages of basic blocks over 80
+/// log mel bins, mean and standard deviation pooled over time, then one
+/// linear layer.
+pub struct SpeakerEncoder {
+    conv1: Conv2d,
+    bn1: BatchNorm,
+    blocks: Vec<BasicBlock>,
+    seg_1: Linear,
+    fbank: Fbank,
+    device: Device,
+}
+
+impl SpeakerEncoder {
+    /// Loads safetensors weights. Block counts and widths are read from the
+    /// weights, so any depth of the same layout loads.
+    pub fn new(model_bytes: Vec<u8>) -> Result<Self, String> {
+        let device = Device::Cpu;
+        let vb = VarBuilder::from_buffered_safetensors(model_bytes, DType::F32, &device)
+            .map_err(|e| format!("VarBuilder error: {e}"))?;
+        // pyannote nests the network under `resnet`
+        let vb = if vb.contains_tensor("resnet.conv1.weight") {
+            vb.pp("resnet")
+        } else {
+            vb
+        };
+        Self::load(vb, device).map_err(|e| format!("Speaker model load error: {e}"))
+    }
+
+    fn load(vb: VarBuilder, device: Device) -> candle_core::Result<Self> {
+        let base = vb.get_unchecked("conv1.weight")?.dim(0)?;
+        let conv1 = conv(1, base, 3, 1, vb.pp("conv1"))?;
+        let bn1 = candle_nn::batch_norm(base, BN_EPS, vb.pp("bn1"))?;
+
+        let mut blocks = Vec::new();
+        let mut in_channels = base;
+        let mut freq = N_MELS;
+        for stage in 0..4 {
+            let channels = base << stage;
+            let stride = if stage == 0 { 1 } else { 2 };
+            let layer = vb.pp(format!("layer{}", stage + 1));
+            let mut index = 0;
+            while layer.contains_tensor(&format!("{index}.conv1.weight")) {
+                let block_stride = if index == 0 { stride } else { 1 };
+                blocks.push(BasicBlock::load(
+                    in_channels,
+                    channels,
+                    block_stride,
+                    layer.pp(index.to_string()),
+                )?);
+                in_channels = channels;
+                index += 1;
+            }
+            if index > 0 {
+                freq = freq.div_ceil(stride);
+            }
+        }
+
+        let embedding_size = vb.get_unchecked("seg_1.weight")?.dim(0)?;
+        let seg_1 = candle_nn::linear(in_channels * freq * 2, embedding_size, vb.pp("seg_1"))?;
+        Ok(Self {
+            conv1,
+            bn1,
+            blocks,
+            seg_1,
+            fbank: Fbank::new(N_MELS),
+            device,
+        })
+    }
+
+    /// L2-normalised embedding of the voice in 16 kHz `pcm`, or `None` if
+    /// the audio is too short to embed.
+    pub fn embed(&mut self, pcm: &[f32]) -> Result<Option<Vec<f32>>, String> {
+        let max_len = (MAX_SEGMENT_SECONDS * SAMPLE_RATE as f64) as usize;
+        let pcm = if pcm.len() > max_len {
+            let start = (pcm.len() - max_len) / 2;
+            &pcm[start..start + max_len]
+        } else {
+            pcm
+        };
+        let (features, n_frames) = self.fbank.compute(pcm);
+        // Pooling needs at least two frames left after downsampling
+        if n_frames < 16 {
+            return Ok(None);
+        }
+
+        let embedding = self
+            .forward(features, n_frames)
+            .map_err(|e| format!("Speaker embedding error: {e}"))?;
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt().max(f32::EPSILON);
+        Ok(Some(embedding.into_iter().map(|x| x / norm).collect()))
+    }
+
+    fn forward(&self, features: Vec<f32>, n_frames: usize) -> candle_core::Result<Vec<f32>> {
+        // (frames, mels) to (batch, channel, mels, frames)
+        let x = Tensor::from_vec(features, (n_frames, N_MELS), &self.device)?
+            .t()?
+            .unsqueeze(0)?
+            .unsqueeze(0)?
+            .contiguous()?;
+        let mut x = self.bn1.forward_t(&self.conv1.forward(&x)?, false)?.relu()?;
+        for block in &self.blocks {
+            x = block.forward(&x)?;
+        }
+
+        let (batch, channels, freq, frames) = x.dims4()?;
+        let x = x.reshape((batch, channels * freq, frames))?;
+        let mean = x.mean(D::Minus1)?;
+        let std = (x.var(D::Minus1)? + VAR_EPS)?.sqrt()?;
+        let stats = Tensor::cat(&[mean, std], 1)?;
+        self.seg_1.forward(&stats)?.squeeze(0)?.to_vec1()
+    }
+}
+
+/// Speakers heard so far, each the running mean of its segments'
+/// embeddings. Segments are assigned as they arrive, so earlier labels are
+/// never revised.
+#[derive(Default)]
+pub struct SpeakerClusters {
+    centroids: Vec<Vec<f32>>,
+    counts: Vec<usize>,
+    last: Option<usize>,
+}
+
+impl SpeakerClusters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of the speaker `embedding` belongs to, starting a new speaker
+    /// if none is similar enough.
+    pub fn assign(&mut self, embedding: &[f32], options: &DiarizationOptions) -> usize {
+        let closest = self
+            .centroids
+            .iter()
+            .map(|c| cosine_similarity(c, embedding))
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        let speaker = match closest {
+            Some((i, similarity))
+                if similarity >= options.threshold
+                    || self.centroids.len() >= options.max_speakers.max(1) =>
+            {
+                let n = self.counts[i] as f32;
+                for (c, &e) in self.centroids[i].iter_mut().zip(embedding) {
+                    *c = (*c * n + e) / (n + 1.0);
+                }
+                self.counts[i] += 1;
+                i
+            }
+            _ => {
+                self.centroids.push(embedding.to_vec());
+                self.counts.push(1);
+                self.centroids.len() - 1
+            }
+        };
+        self.last = Some(speaker);
+        speaker
+    }
+
+    /// The most recently assigned speaker.
+    pub fn last(&self) -> Option<usize> {
+        self.last
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    dot / (norm_a * norm_b).max(f32::EPSILON)
+}
+
+/// Labels transcript segments with speakers from their audio.
+pub struct Diarizer {
+    encoder: SpeakerEncoder,
+    clusters: SpeakerClusters,
+}
+
+impl Diarizer {
+    pub fn new(encoder: SpeakerEncoder) -> Self {
+        Self {
+            encoder,
+            clusters: SpeakerClusters::new(),
+        }
+    }
+
+    /// Speaker of a segment's 16 kHz audio. Segments too short to embed
+    /// keep the previous speaker.
+    pub fn label(
+        &mut self,
+        pcm: &[f32],
+        options: &DiarizationOptions,
+    ) -> Result<Option<usize>, String> {
+        if (pcm.len() as f64) < MIN_SEGMENT_SECONDS * SAMPLE_RATE as f64 {
+            return Ok(self.clusters.last());
+        }
+        Ok(match self.encoder.embed(pcm)? {
+            Some(embedding) => Some(self.clusters.assign(&embedding, options)),
+            None => self.clusters.last(),
+        })
+    }
+
+    /// Labels each of `segments` from `pcm`, whose first sample is at
+    /// `offset` seconds.
+    pub fn label_segments(
+        &mut self,
+        segments: &mut [crate::timestamps::Segment],
+        pcm: &[f32],
+        offset: f64,
+        options: &DiarizationOptions,
+    ) -> Result<(), String> {
+        for segment in segments {
+            let index = |t: f64| {
+                (((t - offset) * SAMPLE_RATE as f64).max(0.0) as usize).min(pcm.len())
+            };
+            let (start, end) = (index(segment.start), index(segment.end));
+            segment.speaker = self.label(&pcm[start..end.max(start)], options)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets every speaker, for a new recording.
+    pub fn reset(&mut self) {
+        self.clusters = SpeakerClusters::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::timestamps::Segment;
+
+    fn options(threshold: f32, max_speakers: usize) -> DiarizationOptions {
+        DiarizationOptions {
+            enabled: true,
+            threshold,
+            max_speakers,
+        }
+    }
+
+    #[test]
+    fn same_voice_is_the_same_speaker() {
+        let mut clusters = SpeakerClusters::new();
+        let options = options(0.5, 8);
+        assert_eq!(clusters.last(), None);
+        assert_eq!(clusters.assign(&[1.0, 0.0, 0.0], &options), 0);
+        assert_eq!(clusters.assign(&[0.9, 0.1, 0.0], &options), 0);
+        assert_eq!(clusters.assign(&[1.0, 0.0, 0.0], &options), 0);
+        assert_eq!(clusters.last(), Some(0));
+    }
+
+    #[test]
+    fn dissimilar_voice_starts_a_speaker() {
+        let mut clusters = SpeakerClusters::new();
+        let options = options(0.5, 8);
+        assert_eq!(clusters.assign(&[1.0, 0.0, 0.0], &options), 0);
+        assert_eq!(clusters.assign(&[0.0, 1.0, 0.0], &options), 1);
+        assert_eq!(clusters.assign(&[0.0, 0.0, 1.0], &options), 2);
+        assert_eq!(clusters.assign(&[0.1, 1.0, 0.0], &options), 1);
+        assert_eq!(clusters.last(), Some(1));
+    }
+
+    #[test]
+    fn speakers_stop_at_the_maximum() {
+        let mut clusters = SpeakerClusters::new();
+        let options = options(0.5, 2);
+        assert_eq!(clusters.assign(&[1.0, 0.0, 0.0], &options), 0);
+        assert_eq!(clusters.assign(&[0.0, 1.0, 0.0], &options), 1);
+        // Unlike both, so it goes to the closest
+        assert_eq!(clusters.assign(&[0.1, 0.2, 1.0], &options), 1);
+        assert_eq!(clusters.assign(&[1.0, 0.0, 0.5], &options), 0);
+        assert_eq!(clusters.counts, [2, 2]);
+    }
+
+    // One channel, no residual blocks and identity weights, so the embedding
+    // is each mel bin's pooled mean and deviation
+    fn identity_encoder() -> SpeakerEncoder {
+        let device = Device::Cpu;
+        let embedding_size = N_MELS * 2;
+        let mut conv1 = vec![0f32; 9];
+        conv1[4] = 1.0;
+        let tensors: HashMap<String, Tensor> = [
+            ("conv1.weight", Tensor::from_vec(conv1, (1, 1, 3, 3), &device)),
+            ("bn1.weight", Tensor::ones(1, DType::F32, &device)),
+            ("bn1.bias", Tensor::zeros(1, DType::F32, &device)),
+            ("bn1.running_mean", Tensor::zeros(1, DType::F32, &device)),
+            ("bn1.running_var", Tensor::ones(1, DType::F32, &device)),
+            ("seg_1.weight", Tensor::eye(embedding_size, DType::F32, &device)),
+            ("seg_1.bias", Tensor::zeros(embedding_size, DType::F32, &device)),
+        ]
+        .into_iter()
+        .map(|(name, tensor)| (name.to_string(), tensor.unwrap()))
+        .collect();
+        let vb = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        SpeakerEncoder::load(vb, device).unwrap()
+    }
+
+    // `seconds` of two tones taking turns every 0.2 s
+    fn voice(low: f64, high: f64, seconds: usize) -> Vec<f32> {
+        let tau = 2.0 * std::f64::consts::PI;
+        (0..seconds * SAMPLE_RATE)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let frequency = if (t / 0.2) as usize % 2 == 0 { low } else { high };
+                (0.3 * (tau * frequency * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn segments_are_labelled_from_their_own_audio() {
+        let (a, b) = (voice(300.0, 1200.0, 2), voice(2500.0, 5000.0, 2));
+        let pcm = [&a[..], &b, &a, &a[..8000]].concat();
+        let segment = |start, end| Segment::new(start, end, String::new(), vec![], vec![]);
+        // The audio starts 10 s into the recording
+        let mut segments = [
+            segment(10.0, 12.0),
+            segment(12.0, 14.0),
+            segment(14.0, 16.0),
+            segment(16.0, 16.5),
+        ];
+
+        let mut diarizer = Diarizer::new(identity_encoder());
+        diarizer.label_segments(&mut segments, &pcm, 10.0, &options(0.9, 8)).unwrap();
+        let speakers: Vec<_> = segments.iter().map(|s| s.speaker).collect();
+        // The last is too short to embed and keeps the previous speaker
+        assert_eq!(speakers, [Some(0), Some(1), Some(0), Some(0)]);
+
+        diarizer.reset();
+        let mut segments = [segment(12.0, 14.0)];
+        diarizer.label_segments(&mut segments, &pcm, 10.0, &options(0.9, 8)).unwrap();
+        assert_eq!(segments[0].speaker, Some(0));
+    }
+}
@@ -1,12 +1,36 @@
+use crate::vad::{Vad, VadEvent};
+
 const SAMPLE_RATE: usize = 16000;
 const CHUNK_SECONDS: usize = 30;
 const INFERENCE_INTERVAL_SECONDS: usize = 3;
 const MAX_SAMPLES: usize = SAMPLE_RATE * CHUNK_SECONDS;
 const INFERENCE_THRESHOLD: usize = SAMPLE_RATE * INFERENCE_INTERVAL_SECONDS;
+// Mel hop; the window only slides by whole frames so cached mel frames stay
+// aligned
+const HOP_LENGTH: usize = 160;
+// How far past the required drop the window may slide to start at a pause
+const MAX_PAUSE_SEARCH: usize = SAMPLE_RATE * 5;
 
 pub struct StreamingBuffer {
     buffer: Vec<f32>,
     last_inference_pos: usize,
+    // Samples dropped off the front of the rolling window so far
+    dropped_samples: usize,
+    vad: Vad,
+    // Speech was heard since the last chunk was taken
+    speech_since_inference: bool,
+    // Speech ended since the last chunk was taken
+    pause_pending: bool,
+    // Whether the last chunk ended in a pause after speech
+    chunk_ends_in_pause: bool,
+    // Stream positions where speech ended, for cutting the window
+    pauses: Vec<usize>,
+}
+
+impl Default for StreamingBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StreamingBuffer {
@@ -14,35 +38,125 @@ impl StreamingBuffer {
         Self {
             buffer: Vec::with_capacity(MAX_SAMPLES),
             last_inference_pos: 0,
+            dropped_samples: 0,
+            vad: Vad::new(),
+            speech_since_inference: false,
+            pause_pending: false,
+            chunk_ends_in_pause: false,
+            pauses: Vec::new(),
         }
     }
 
-    pub fn push(&mut self, pcm: &[f32]) {
+    /// Appends audio and returns the speech/silence changes it contains.
+    pub fn push(&mut self, pcm: &[f32]) -> Vec<VadEvent> {
         self.buffer.extend_from_slice(pcm);
 
-        // Keep rolling window of 30 seconds
+        let events = self.vad.process(pcm);
+        for event in &events {
+            if event.speaking {
+                self.speech_since_inference = true;
+            } else {
+                self.pause_pending = true;
+                self.pauses.push((event.time * SAMPLE_RATE as f64) as usize);
+            }
+        }
+        if self.vad.is_speaking() {
+            self.speech_since_inference = true;
+        }
+
+        // Keep rolling window of 30 seconds, starting at a pause if one is
+        // close enough
         if self.buffer.len() > MAX_SAMPLES {
             let excess = self.buffer.len() - MAX_SAMPLES;
-            self.buffer.drain(..excess);
-            self.last_inference_pos = self.last_inference_pos.saturating_sub(excess);
+            let pause = self
+                .pauses
+                .iter()
+                .map(|&p| p.saturating_sub(self.dropped_samples))
+                .find(|&p| p >= excess && p <= excess + MAX_PAUSE_SEARCH);
+            self.drop_front(pause.unwrap_or(excess).next_multiple_of(HOP_LENGTH));
         }
+
+        events
+    }
+
+    /// Drops buffered audio before stream time `seconds`, rounded down to a
+    /// whole mel frame.
+    pub fn trim_before(&mut self, seconds: f64) {
+        let target = (seconds * SAMPLE_RATE as f64) as usize;
+        let n = target
+            .saturating_sub(self.dropped_samples)
+            .min(self.buffer.len());
+        self.drop_front(n - n % HOP_LENGTH);
+    }
+
+    fn drop_front(&mut self, n: usize) {
+        let n = n.min(self.buffer.len());
+        self.buffer.drain(..n);
+        self.dropped_samples += n;
+        self.last_inference_pos = self.last_inference_pos.saturating_sub(n);
+        let dropped = self.dropped_samples;
+        self.pauses.retain(|&p| p > dropped);
     }
 
+    /// True once there is new speech to decode: right after a pause, so
+    /// windows end between words, or every few seconds while speech goes on.
+    /// Silence alone never triggers inference.
     pub fn should_transcribe(&self) -> bool {
-        self.buffer.len() - self.last_inference_pos >= INFERENCE_THRESHOLD
+        self.speech_since_inference
+            && (self.pause_pending
+                || self.buffer.len() - self.last_inference_pos >= INFERENCE_THRESHOLD)
     }
 
     pub fn get_chunk(&mut self) -> Vec<f32> {
-        if !self.should_transcribe() && self.buffer.len() < INFERENCE_THRESHOLD {
+        if !self.should_transcribe() {
             return Vec::new();
         }
         self.last_inference_pos = self.buffer.len();
+        self.chunk_ends_in_pause = self.pause_pending && !self.vad.is_speaking();
+        self.speech_since_inference = self.vad.is_speaking();
+        self.pause_pending = false;
         self.buffer.clone()
     }
 
+    /// Takes the whole buffer once the input stops, treated as ending in a
+    /// pause, if speech went undecoded or `force` is set. Empty otherwise.
+    pub fn get_final_chunk(&mut self, force: bool) -> Vec<f32> {
+        if !self.speech_since_inference && !force {
+            return Vec::new();
+        }
+        self.last_inference_pos = self.buffer.len();
+        self.chunk_ends_in_pause = true;
+        self.speech_since_inference = false;
+        self.pause_pending = false;
+        self.buffer.clone()
+    }
+
+    /// Whether the last chunk ended in silence after speech, so nothing
+    /// later can change how its final words are heard.
+    pub fn ends_in_pause(&self) -> bool {
+        self.chunk_ends_in_pause
+    }
+
     pub fn clear(&mut self) {
         self.buffer.clear();
         self.last_inference_pos = 0;
+        self.dropped_samples = 0;
+        self.vad.reset();
+        self.speech_since_inference = false;
+        self.pause_pending = false;
+        self.chunk_ends_in_pause = false;
+        self.pauses.clear();
+    }
+
+    /// Stream time of the first buffered sample, in seconds since the last
+    /// `clear`.
+    pub fn start_seconds(&self) -> f64 {
+        self.dropped_samples as f64 / SAMPLE_RATE as f64
+    }
+
+    /// Stream position of the first buffered sample.
+    pub fn start_sample(&self) -> usize {
+        self.dropped_samples
     }
 
     pub fn duration_seconds(&self) -> f64 {
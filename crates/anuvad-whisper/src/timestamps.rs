@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+
+use crate::channels::Channel;
+use crate::decoding;
+
+// Seconds per timestamp token step
+pub const TIME_PRECISION: f64 = 0.02;
+// The first timestamp may be at most 1 second in
+const MAX_INITIAL_TIMESTAMP_INDEX: u32 = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub tokens: Vec<u32>,
+    /// Log-probability of each of `tokens` when it was decoded.
+    #[serde(default)]
+    pub token_logprobs: Vec<f32>,
+    #[serde(default)]
+    pub avg_logprob: f64,
+    /// Index of the speaker heard, when diarization is on.
+    #[serde(default)]
+    pub speaker: Option<usize>,
+    /// Input the segment was heard on, when inputs are kept apart.
+    #[serde(default)]
+    pub channel: Option<Channel>,
+}
+
+impl Segment {
+    pub fn new(
+        start: f64,
+        end: f64,
+        text: String,
+        tokens: Vec<u32>,
+        token_logprobs: Vec<f32>,
+    ) -> Self {
+        Self {
+            start,
+            end,
+            text,
+            tokens,
+            avg_logprob: decoding::mean(&token_logprobs),
+            token_logprobs,
+            speaker: None,
+            channel: None,
+        }
+    }
+}
+
+/// Token ids the timestamp rules need.
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampTokens {
+    pub eot: u32,
+    pub no_timestamps: u32,
+    pub timestamp_begin: u32,
+}
+
+impl TimestampTokens {
+    pub fn is_timestamp(&self, token: u32) -> bool {
+        token >= self.timestamp_begin
+    }
+
+    pub fn to_seconds(&self, token: u32) -> f64 {
+        (token - self.timestamp_begin) as f64 * TIME_PRECISION
+    }
+}
+
+/// Masks `logits` so the next token follows Whisper's timestamp rules, given
+/// the tokens sampled so far (without the prompt).
+pub fn apply_timestamp_rules(logits: &mut [f32], sampled: &[u32], tokens: &TimestampTokens) {
+    let vocab = logits.len();
+    let ts_begin = (tokens.timestamp_begin as usize).min(vocab);
+    let eot = (tokens.eot as usize).min(vocab);
+
+    if let Some(l) = logits.get_mut(tokens.no_timestamps as usize) {
+        *l = f32::NEG_INFINITY;
+    }
+
+    // Timestamps come in pairs, except directly before EOT
+    let last_was_timestamp = sampled.last().is_some_and(|&t| tokens.is_timestamp(t));
+    let penultimate_was_timestamp =
+        sampled.len() < 2 || tokens.is_timestamp(sampled[sampled.len() - 2]);
+    if last_was_timestamp {
+        if penultimate_was_timestamp {
+            suppress(&mut logits[ts_begin..]);
+        } else {
+            suppress(&mut logits[..eot]);
+        }
+    }
+
+    // Timestamps never go backwards
+    if let Some(&last_ts) = sampled.iter().rev().find(|&&t| tokens.is_timestamp(t)) {
+        let min_ts = if last_was_timestamp && !penultimate_was_timestamp {
+            last_ts
+        } else {
+            last_ts + 1
+        };
+        suppress(&mut logits[ts_begin..(min_ts as usize).min(vocab)]);
+    }
+
+    // The first token is always a timestamp, and not too far in
+    if sampled.is_empty() {
+        suppress(&mut logits[..ts_begin]);
+        let last_allowed = ts_begin + MAX_INITIAL_TIMESTAMP_INDEX as usize;
+        suppress(&mut logits[(last_allowed + 1).min(vocab)..]);
+    }
+
+    // Pick a timestamp when their total probability beats any text token
+    let text_max = logits[..ts_begin]
+        .iter()
+        .cloned()
+        .fold(f32::NEG_INFINITY, f32::max);
+    if log_sum_exp(&logits[ts_begin..]) > text_max {
+        suppress(&mut logits[..ts_begin]);
+    }
+}
+
+/// Splits sampled tokens, with their log-probabilities, into timed
+/// segments. Text after the last timestamp runs to `duration`.
+pub fn split_segments(
+    sampled: &[u32],
+    logprobs: &[f32],
+    tokens: &TimestampTokens,
+    duration: f64,
+    decode: impl Fn(&[u32]) -> Result<String, String>,
+) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut start: Option<f64> = None;
+    let mut text_tokens = Vec::new();
+    let mut text_logprobs = Vec::new();
+
+    for (&token, &logprob) in sampled.iter().zip(logprobs) {
+        if token == tokens.eot {
+            break;
+        }
+        if !tokens.is_timestamp(token) {
+            text_tokens.push(token);
+            text_logprobs.push(logprob);
+            continue;
+        }
+        let time = tokens.to_seconds(token);
+        match start {
+            Some(seg_start) if !text_tokens.is_empty() => {
+                segments.push(Segment::new(
+                    seg_start,
+                    time,
+                    decode(&text_tokens)?,
+                    std::mem::take(&mut text_tokens),
+                    std::mem::take(&mut text_logprobs),
+                ));
+                start = None;
+            }
+            _ => start = Some(time),
+        }
+    }
+
+    if !text_tokens.is_empty() {
+        let seg_start = start
+            .or_else(|| segments.last().map(|s: &Segment| s.end))
+            .unwrap_or(0.0);
+        segments.push(Segment::new(
+            seg_start,
+            duration.max(seg_start),
+            decode(&text_tokens)?,
+            text_tokens,
+            text_logprobs,
+        ));
+    }
+
+    Ok(segments)
+}
+
+/// How far into a long-form window the next one should start, in seconds.
+/// When the window ends mid-segment, that is the last timestamp pair, so
+/// the unfinished text is decoded again with its full audio; otherwise the
+/// whole window is consumed.
+pub fn seek_offset(sampled: &[u32], tokens: &TimestampTokens, duration: f64) -> f64 {
+    let is_timestamp: Vec<bool> = sampled.iter().map(|&t| tokens.is_timestamp(t)).collect();
+    let single_timestamp_ending = is_timestamp.ends_with(&[false, true]);
+    let last_pair = is_timestamp.windows(2).rposition(|w| w[0] && w[1]);
+    match last_pair {
+        Some(i) if !single_timestamp_ending => tokens.to_seconds(sampled[i]).min(duration),
+        _ => duration,
+    }
+}
+
+fn suppress(logits: &mut [f32]) {
+    logits.fill(f32::NEG_INFINITY);
+}
+
+fn log_sum_exp(values: &[f32]) -> f32 {
+    let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    if max == f32::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f32>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKENS: TimestampTokens = TimestampTokens {
+        eot: 10,
+        no_timestamps: 11,
+        timestamp_begin: 12,
+    };
+    const VOCAB: usize = 12 + 1501;
+
+    // Timestamp token for `seconds`
+    fn ts(seconds: f64) -> u32 {
+        TOKENS.timestamp_begin + (seconds / TIME_PRECISION).round() as u32
+    }
+
+    fn masked(sampled: &[u32]) -> Vec<f32> {
+        let mut logits = vec![0.0f32; VOCAB];
+        // Text outweighs all timestamps together
+        logits[..TOKENS.timestamp_begin as usize].fill(10.0);
+        apply_timestamp_rules(&mut logits, sampled, &TOKENS);
+        logits
+    }
+
+    fn allowed(logits: &[f32], token: u32) -> bool {
+        logits[token as usize] > f32::NEG_INFINITY
+    }
+
+    #[test]
+    fn starts_with_an_early_timestamp() {
+        let logits = masked(&[]);
+        assert!(!allowed(&logits, 3));
+        assert!(!allowed(&logits, TOKENS.eot));
+        assert!(!allowed(&logits, TOKENS.no_timestamps));
+        assert!(allowed(&logits, ts(0.0)));
+        assert!(allowed(&logits, ts(1.0)));
+        assert!(!allowed(&logits, ts(1.02)));
+    }
+
+    #[test]
+    fn timestamps_pair_up_and_never_go_back() {
+        // Text after an opening timestamp: more text or a later timestamp
+        let logits = masked(&[ts(0.5), 3]);
+        assert!(allowed(&logits, 4));
+        assert!(!allowed(&logits, ts(0.5)));
+        assert!(allowed(&logits, ts(0.52)));
+
+        // A closing timestamp: the next must open at or after it, or end
+        let logits = masked(&[ts(0.5), 3, ts(1.0)]);
+        assert!(!allowed(&logits, 4));
+        assert!(allowed(&logits, TOKENS.eot));
+        assert!(!allowed(&logits, ts(0.98)));
+        assert!(allowed(&logits, ts(1.0)));
+
+        // A full pair: text or the end follows
+        let logits = masked(&[ts(0.5), 3, ts(1.0), ts(1.0)]);
+        assert!(allowed(&logits, 4));
+        assert!(allowed(&logits, TOKENS.eot));
+        assert!(!allowed(&logits, ts(1.2)));
+    }
+
+    #[test]
+    fn timestamp_mass_beats_text() {
+        let mut logits = vec![0.0f32; VOCAB];
+        logits[3] = 5.0;
+        apply_timestamp_rules(&mut logits, &[ts(0.0), 3], &TOKENS);
+        // 1500 timestamps at 0 outweigh one text token at 5
+        assert!(!allowed(&logits, 3));
+        assert!(allowed(&logits, ts(2.0)));
+    }
+
+    #[test]
+    fn splits_segments_at_timestamp_pairs() {
+        let sampled = [ts(0.0), 1, 2, ts(0.5), ts(0.5), 3, ts(1.0), 4, TOKENS.eot, 5];
+        let logprobs = [0.0, -1.0, -3.0, 0.0, 0.0, -0.5, 0.0, -0.25, 0.0, 0.0];
+        let decode = |t: &[u32]| Ok(t.iter().map(|&t| (b'a' + t as u8) as char).collect());
+        let segments = split_segments(&sampled, &logprobs, &TOKENS, 1.8, decode).unwrap();
+
+        let spans: Vec<_> = segments.iter().map(|s| (s.start, s.end, s.text.as_str())).collect();
+        assert_eq!(spans, [(0.0, 0.5, "bc"), (0.5, 1.0, "d"), (1.0, 1.8, "e")]);
+        assert_eq!(segments[0].tokens, [1, 2]);
+        assert_eq!(segments[0].token_logprobs, [-1.0, -3.0]);
+        assert!((segments[0].avg_logprob + 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn seeks_to_the_last_timestamp_pair() {
+        // An unfinished segment is decoded again from its start
+        let sampled = [ts(0.0), 1, ts(4.0), ts(4.0), 2];
+        assert_eq!(seek_offset(&sampled, &TOKENS, 30.0), 4.0);
+        let sampled = [ts(0.0), 1, ts(4.0), ts(4.0), 2, ts(9.0), ts(9.0)];
+        assert_eq!(seek_offset(&sampled, &TOKENS, 30.0), 9.0);
+        assert_eq!(seek_offset(&sampled, &TOKENS, 6.0), 6.0);
+    }
+
+    #[test]
+    fn consumes_the_window_when_segments_close() {
+        // A lone closing timestamp means the last segment finished
+        let sampled = [ts(0.0), 1, ts(4.0), ts(4.0), 2, ts(9.0)];
+        assert_eq!(seek_offset(&sampled, &TOKENS, 30.0), 30.0);
+        assert_eq!(seek_offset(&[ts(0.0), 1, 2], &TOKENS, 30.0), 30.0);
+        assert_eq!(seek_offset(&[], &TOKENS, 12.5), 12.5);
+    }
+}
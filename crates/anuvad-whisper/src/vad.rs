@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+const SAMPLE_RATE: usize = 16000;
+// 30 ms analysis frames
+const FRAME_SAMPLES: usize = 480;
+// Frames above the floor needed to start speech (90 ms), so clicks don't
+const ONSET_FRAMES: usize = 3;
+// Quiet frames needed to end speech (510 ms), so short gaps between words
+// don't
+const HANGOVER_FRAMES: usize = 17;
+// How far above the noise floor a frame must be to count as speech
+const THRESHOLD_DB: f32 = 9.0;
+// Frames quieter than this are never speech
+const MIN_LEVEL_DB: f32 = -60.0;
+// The floor follows quieter frames quickly and louder ones slowly
+const FLOOR_FALL_RATE: f32 = 0.2;
+const FLOOR_RISE_RATE: f32 = 0.005;
+
+/// A change between speech and silence, in seconds since the stream began.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct VadEvent {
+    pub speaking: bool,
+    pub time: f64,
+}
+
+/// Energy-based voice activity detector with an adaptive noise floor.
+#[derive(Debug, Default)]
+pub struct Vad {
+    pending: Vec<f32>,
+    frames_seen: usize,
+    noise_floor_db: Option<f32>,
+    speaking: bool,
+    run: usize,
+}
+
+impl Vad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    /// Classifies 16 kHz samples and returns the speech/silence changes
+    /// they contain.
+    pub fn process(&mut self, pcm: &[f32]) -> Vec<VadEvent> {
+        self.pending.extend_from_slice(pcm);
+        let mut events = Vec::new();
+
+        let n_frames = self.pending.len() / FRAME_SAMPLES;
+        for i in 0..n_frames {
+            let frame = &self.pending[i * FRAME_SAMPLES..(i + 1) * FRAME_SAMPLES];
+            let energy = frame.iter().map(|s| s * s).sum::<f32>() / FRAME_SAMPLES as f32;
+            let level_db = 10.0 * (energy + 1e-12).log10();
+
+            let floor = self.noise_floor_db.get_or_insert(level_db);
+            let active = level_db > (*floor + THRESHOLD_DB).max(MIN_LEVEL_DB);
+            let rate = if level_db < *floor {
+                FLOOR_FALL_RATE
+            } else {
+                FLOOR_RISE_RATE
+            };
+            *floor += rate * (level_db - *floor);
+
+            // `run` counts frames that disagree with the current state
+            let frame_idx = self.frames_seen + i;
+            if active != self.speaking {
+                self.run += 1;
+                let needed = if self.speaking {
+                    HANGOVER_FRAMES
+                } else {
+                    ONSET_FRAMES
+                };
+                if self.run >= needed {
+                    self.speaking = active;
+                    let first_frame = frame_idx + 1 - self.run;
+                    events.push(VadEvent {
+                        speaking: active,
+                        time: (first_frame * FRAME_SAMPLES) as f64 / SAMPLE_RATE as f64,
+                    });
+                    self.run = 0;
+                }
+            } else {
+                self.run = 0;
+            }
+        }
+
+        self.pending.drain(..n_frames * FRAME_SAMPLES);
+        self.frames_seen += n_frames;
+        events
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quiet noise, then `loud` frames of a tone, then quiet again
+    fn signal(quiet: usize, loud: usize, after: usize) -> Vec<f32> {
+        let mut state = 1u32;
+        (0..(quiet + loud + after) * FRAME_SAMPLES)
+            .map(|i| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = 0.003 * (state as f32 / u32::MAX as f32 - 0.5);
+                let frame = i / FRAME_SAMPLES;
+                if (quiet..quiet + loud).contains(&frame) {
+                    noise + 0.3 * (i as f32 * 0.17).sin()
+                } else {
+                    noise
+                }
+            })
+            .collect()
+    }
+
+    fn frame_time(frame: usize) -> f64 {
+        (frame * FRAME_SAMPLES) as f64 / SAMPLE_RATE as f64
+    }
+
+    #[test]
+    fn detects_speech_start_and_end() {
+        let mut vad = Vad::new();
+        let events = vad.process(&signal(30, 40, 30));
+        assert_eq!(
+            events,
+            [
+                VadEvent {
+                    speaking: true,
+                    time: frame_time(30)
+                },
+                VadEvent {
+                    speaking: false,
+                    time: frame_time(70)
+                },
+            ]
+        );
+        assert!(!vad.is_speaking());
+    }
+
+    #[test]
+    fn ignores_clicks_and_short_gaps() {
+        let mut vad = Vad::new();
+        assert!(vad.process(&signal(30, ONSET_FRAMES - 1, 30)).is_empty());
+
+        let mut vad = Vad::new();
+        let mut pcm = signal(30, 20, HANGOVER_FRAMES - 1);
+        pcm.extend(signal(0, 20, 0));
+        let events = vad.process(&pcm);
+        assert_eq!(events.len(), 1);
+        assert!(vad.is_speaking());
+    }
+
+    #[test]
+    fn chunking_does_not_change_events() {
+        let pcm = signal(30, 40, 30);
+        let whole = Vad::new().process(&pcm);
+        let mut vad = Vad::new();
+        let chunked: Vec<VadEvent> = pcm.chunks(1000).flat_map(|c| vad.process(c)).collect();
+        assert_eq!(chunked, whole);
+    }
+}
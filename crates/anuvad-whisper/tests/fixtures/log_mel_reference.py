@@ -0,0 +1,96 @@
+"""Writes log_mel_reference.txt for the audio::tests log-mel golden test.
+
+A pure-Python, float64 transcription of openai-whisper's
+`whisper.audio.log_mel_spectrogram` (torch.stft with center=True and
+reflect padding, periodic Hann window, last frame dropped, |X|^2, mel
+filterbank from `librosa.filters.mel(sr=16000, n_fft=400, n_mels=80)`,
+log10 clamped at 1e-10, floored at max - 8, then (x + 4) / 4), so it runs
+without numpy or torch.
+
+The input is 0.5 s of a 440 Hz tone plus a 3 kHz tone fading in, padded
+to 30 s. Only the first FRAMES frames are written; every later frame is
+padding and equals the floor, written on the header line.
+
+Usage: python3 log_mel_reference.py > log_mel_reference.txt
+"""
+import math
+
+SAMPLE_RATE = 16000
+N_FFT = 400
+HOP_LENGTH = 160
+N_SAMPLES = 30 * SAMPLE_RATE
+N_FREQS = N_FFT // 2 + 1
+N_MELS = 80
+FRAMES = 56
+
+
+def signal(n):
+    return [
+        0.3 * math.sin(2 * math.pi * 440 * i / SAMPLE_RATE)
+        + 0.2 * math.sin(2 * math.pi * 3000 * i / SAMPLE_RATE + 0.5) * (i / n)
+        for i in range(n)
+    ]
+
+
+# librosa.filters.mel with htk=False (Slaney) and norm="slaney"
+def hz_to_mel(hz):
+    f_sp, min_log_hz = 200 / 3, 1000.0
+    if hz < min_log_hz:
+        return hz / f_sp
+    return min_log_hz / f_sp + math.log(hz / min_log_hz) / (math.log(6.4) / 27)
+
+
+def mel_to_hz(mel):
+    f_sp, min_log_hz = 200 / 3, 1000.0
+    min_log_mel = min_log_hz / f_sp
+    if mel < min_log_mel:
+        return f_sp * mel
+    return min_log_hz * math.exp(math.log(6.4) / 27 * (mel - min_log_mel))
+
+
+def mel_filters():
+    fft_freqs = [SAMPLE_RATE / 2 * k / (N_FREQS - 1) for k in range(N_FREQS)]
+    top = hz_to_mel(SAMPLE_RATE / 2)
+    mel_f = [mel_to_hz(top * i / (N_MELS + 1)) for i in range(N_MELS + 2)]
+    filters = []
+    for i in range(N_MELS):
+        enorm = 2.0 / (mel_f[i + 2] - mel_f[i])
+        row = []
+        for f in fft_freqs:
+            lower = (f - mel_f[i]) / (mel_f[i + 1] - mel_f[i])
+            upper = (mel_f[i + 2] - f) / (mel_f[i + 2] - mel_f[i + 1])
+            row.append(max(0.0, min(lower, upper)) * enorm)
+        filters.append(row)
+    return filters
+
+
+def log10_mel_frames(pcm):
+    x = (pcm + [0.0] * N_SAMPLES)[:N_SAMPLES]
+    half = N_FFT // 2
+    padded = [x[half - i] for i in range(half)] + x
+    window = [0.5 * (1 - math.cos(2 * math.pi * n / N_FFT)) for n in range(N_FFT)]
+    filters = mel_filters()
+    cos = [[math.cos(2 * math.pi * k * n / N_FFT) for n in range(N_FFT)] for k in range(N_FREQS)]
+    sin = [[math.sin(2 * math.pi * k * n / N_FFT) for n in range(N_FFT)] for k in range(N_FREQS)]
+    frames = []
+    for t in range(FRAMES):
+        frame = [padded[t * HOP_LENGTH + n] * window[n] for n in range(N_FFT)]
+        power = []
+        for k in range(N_FREQS):
+            re = sum(a * b for a, b in zip(frame, cos[k]))
+            im = sum(a * b for a, b in zip(frame, sin[k]))
+            power.append(re * re + im * im)
+        frames.append(
+            [math.log10(max(1e-10, sum(w * p for w, p in zip(row, power)))) for row in filters]
+        )
+    return frames
+
+
+if __name__ == "__main__":
+    frames = log10_mel_frames(signal(SAMPLE_RATE // 2))
+    # Padding frames are log10(1e-10) = -10, below any frame written here
+    peak = max(max(f) for f in frames)
+    normalize = lambda v: (max(v, peak - 8.0) + 4.0) / 4.0
+    print(f"# {N_MELS} mels x {FRAMES} frames; later frames are {normalize(-10.0):.6f}")
+    for m in range(N_MELS):
+        print(" ".join(f"{normalize(f[m]):.6f}" for f in frames))
@@ -2,12 +2,97 @@ import init, { WhisperWorker } from './pkg_whisper/anuvad_whisper.js';
 
 let worker = null;
 
+// Set by the main thread to stop a running decode; needs a cross-origin
+// isolated page for SharedArrayBuffer
+const cancelFlag = self.crossOriginIsolated
+    ? new Int32Array(new SharedArrayBuffer(4))
+    : null;
+
+// Transcription requests per channel ('' for the shared stream) waiting for
+// a decode. Audio is buffered as it arrives; each channel is then decoded
+// once however many requests piled up behind the running decode.
+const pending = new Map();
+let draining = false;
+// Filled when recording stops: after the running decode, each of these
+// channels' remaining audio is decoded once more and everything heard is
+// committed
+const flushing = new Set();
+const CHANNELS = ['', 'me', 'remote'];
+
 async function initWorker() {
     await init();
     worker = new WhisperWorker();
+    if (cancelFlag) {
+        worker.set_cancel_flag(cancelFlag);
+        self.postMessage({ type: 'CancelFlag', flag: cancelFlag });
+    }
     console.log('[WhisperWorker] Initialized');
 }
 
+function queueDepth() {
+    let depth = 0;
+    for (const count of pending.values()) depth += count;
+    return depth;
+}
+
+function postQueueDepth() {
+    self.postMessage({ type: 'QueueDepth', depth: queueDepth() });
+}
+
+function scheduleDrain() {
+    if (draining) return;
+    draining = true;
+    setTimeout(drain, 0);
+}
+
+function postUpdate(result) {
+    if (!result) return;
+    // Committed text is appended; the partial replaces the last one
+    self.postMessage({
+        type: 'TranscriptionResult',
+        text: result.text,
+        language: result.language || null,
+        language_probability: result.language_probability ?? null,
+        segments: result.segments || [],
+        words: result.words || [],
+        safeguards: result.safeguards || [],
+        clipping: result.clipping || 0,
+        channel: result.channel || null
+    });
+    self.postMessage({
+        type: 'TranscriptionPartial',
+        text: result.partial,
+        channel: result.channel || null
+    });
+}
+
+async function drain() {
+    try {
+        while (pending.size > 0 || flushing.size > 0) {
+            if (pending.size > 0) {
+                const [channel] = pending.keys();
+                pending.delete(channel);
+                postQueueDepth();
+                postUpdate(worker.transcribe(channel || undefined));
+            } else {
+                const [channel] = flushing;
+                flushing.delete(channel);
+                postUpdate(worker.finish(channel || undefined));
+            }
+
+            // Let audio and cancellations queued during the decode in
+            await new Promise(resolve => setTimeout(resolve, 0));
+        }
+    } catch (e) {
+        pending.clear();
+        flushing.clear();
+        postQueueDepth();
+        self.postMessage({ type: 'Error', message: String(e) });
+    } finally {
+        draining = false;
+    }
+}
+
 self.onmessage = async function(event) {
     const msg = event.data;
 
@@ -15,13 +100,75 @@ self.onmessage = async function(event) {
         switch (msg.type) {
             case 'LoadModel': {
                 if (!worker) await initWorker();
-                worker.load_model(
+                const report = worker.load_model(
                     msg.model_bytes,
                     msg.tokenizer_json,
                     msg.config_json,
-                    msg.mel_bytes
+                    msg.precision || 'f32',
+                    msg.generation_config_json || undefined
                 );
-                self.postMessage({ type: 'ModelLoaded' });
+                self.postMessage({ type: 'ModelLoaded', report });
+                break;
+            }
+
+            case 'SetLanguage': {
+                if (!worker) await initWorker();
+                worker.set_language(msg.language, msg.allowed_languages || []);
+                break;
+            }
+
+            case 'SetTask': {
+                if (!worker) await initWorker();
+                worker.set_task(msg.task);
+                break;
+            }
+
+            case 'SetTimestamps': {
+                if (!worker) await initWorker();
+                worker.set_timestamps(msg.enabled);
+                break;
+            }
+
+            case 'SetWordTimestamps': {
+                if (!worker) await initWorker();
+                worker.set_word_timestamps(msg.enabled);
+                break;
+            }
+
+            case 'SetDecoding': {
+                if (!worker) await initWorker();
+                worker.set_decoding(msg.decoding);
+                break;
+            }
+
+            case 'SetPrompt': {
+                if (!worker) await initWorker();
+                worker.set_prompt(msg.prompt);
+                break;
+            }
+
+            case 'SetSafeguards': {
+                if (!worker) await initWorker();
+                worker.set_safeguards(msg.safeguards);
+                break;
+            }
+
+            case 'SetDsp': {
+                if (!worker) await initWorker();
+                worker.set_dsp(msg.dsp);
+                break;
+            }
+
+            case 'LoadSpeakerModel': {
+                if (!worker) await initWorker();
+                worker.load_speaker_model(new Uint8Array(msg.model_bytes));
+                self.postMessage({ type: 'SpeakerModelLoaded' });
+                break;
+            }
+
+            case 'SetDiarization': {
+                if (!worker) await initWorker();
+                worker.set_diarization(msg.diarization);
                 break;
             }
 
@@ -30,15 +177,92 @@ self.onmessage = async function(event) {
                     self.postMessage({ type: 'Error', message: 'Worker not initialized' });
                     return;
                 }
-                worker.push_audio(new Float32Array(msg.audio));
-                const result = worker.transcribe();
-                if (result) {
+                const channel = msg.channel || '';
+                const events = worker.push_audio(
+                    new Float32Array(msg.audio),
+                    msg.sample_rate || 16000,
+                    channel || undefined
+                );
+                for (const e of events) {
                     self.postMessage({
-                        type: 'TranscriptionResult',
-                        text: result.text,
-                        language: result.language || null
+                        type: 'VoiceActivity',
+                        speaking: e.speaking,
+                        time: e.time,
+                        channel: msg.channel || null
                     });
                 }
+                pending.set(channel, (pending.get(channel) || 0) + 1);
+                postQueueDepth();
+                scheduleDrain();
+                break;
+            }
+
+            case 'Cancel': {
+                if (!worker) break;
+                pending.clear();
+                flushing.clear();
+                worker.cancel();
+                postQueueDepth();
+                break;
+            }
+
+            case 'Flush': {
+                if (!worker) break;
+                pending.clear();
+                for (const channel of CHANNELS) flushing.add(channel);
+                postQueueDepth();
+                scheduleDrain();
+                break;
+            }
+
+            case 'Reset': {
+                if (!worker) break;
+                // Nothing queued for the last recording may reach the new
+                // one; its flush still commits what it heard
+                const channel = msg.channel || '';
+                pending.delete(channel);
+                if (flushing.delete(channel)) {
+                    postUpdate(worker.finish(channel || undefined));
+                }
+                worker.reset(channel || undefined);
+                postQueueDepth();
+                break;
+            }
+
+            case 'TranscribeLong': {
+                if (!worker) {
+                    self.postMessage({ type: 'Error', message: 'Worker not initialized' });
+                    return;
+                }
+                const segments = worker.transcribe_long(new Float32Array(msg.audio));
+                if (!segments) break;
+                self.postMessage({
+                    type: 'TranscriptionResult',
+                    text: segments.map(s => s.text).join(' '),
+                    language: null,
+                    language_probability: null,
+                    segments
+                });
+                break;
+            }
+
+            case 'TranscribeFile': {
+                if (!worker) {
+                    self.postMessage({ type: 'Error', message: 'Worker not initialized' });
+                    return;
+                }
+                const segments = worker.transcribe_file(
+                    new Uint8Array(msg.bytes),
+                    msg.extension || undefined
+                );
+                if (!segments) break;
+                self.postMessage({
+                    type: 'TranscriptionResult',
+                    text: segments.map(s => s.text).join(' '),
+                    language: null,
+                    language_probability: null,
+                    segments
+                });
                 break;
             }
 